@@ -1,6 +1,9 @@
 #!/bin/bash
 . "$HOME/.cargo/env"
 
+# The firmware is its own workspace so that it can build for the board.
+cd firmware
+
 export CARGO_TARGET_DIR=target_docker
 
 cargo clean
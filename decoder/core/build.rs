@@ -0,0 +1,211 @@
+//! This build script brings the deployment secrets in from /global.secrets,
+//! deriving this decoder's keys from the `DECODER_ID` environment variable, and
+//! writes them out as constants for `crypto.rs` to include. The build fails if
+//! either is missing, unless a host build opts in to the test deployment's
+//! secrets with `DECODER_TEST_SECRETS=1`. To read what the first release saved,
+//! it also needs the random flash key that the decoder's old build made, in hex
+//! as `LEGACY_FLASH_KEY` (see [`legacy_flash_key`]). It also writes out what the
+//! decoder reports about itself for `info.rs`, and how many subscriptions it has
+//! room for (`MAX_SUBSCRIPTIONS`, 8 if unset) and how many frame timestamps
+//! make up a second (`TIMESTAMPS_PER_SECOND`, 1000000 if unset, for the
+//! uplink's microseconds) for `decoder.rs`.
+
+use std::env::{self, var};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use decoder_secrets::{parse_decoder_id, Secrets};
+use ed25519_dalek::VerifyingKey;
+
+/// The legacy flash key that host builds with the test secrets get, so the
+/// tests can make storage the way the old firmware did.
+const TEST_LEGACY_FLASH_KEY: [u8; 32] = [0x1e; 32];
+
+fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    // I'm (ab)using build.rs for bringing secrets and stuff in. So I'm just
+    // forcing a rerun of build.rs everytime.
+
+    // Thanks to this random forum post for the bad idea
+    // https://users.rust-lang.org/t/how-can-i-make-build-rs-rerun-every-time-that-cargo-run-or-cargo-build-is-run/51852/5
+
+    std::env::set_var("REBUILD", format!("{:?}", std::time::Instant::now()));
+    println!("cargo::rerun-if-env-changed=REBUILD");
+    println!("cargo::warning=ran build.rs");
+
+    // The board build always uses /global.secrets, but it can be pointed
+    // elsewhere for host builds (like the simulator).
+    let mut secrets_path =
+        PathBuf::from(var("GLOBAL_SECRETS").unwrap_or_else(|_| "/global.secrets".to_string()));
+    let mut decoder_id = var("DECODER_ID");
+    let mut test_secrets = false;
+
+    // Host builds can opt in to the test deployment's secrets instead, which
+    // keeps rust-analyzer useful and gives the host tests keys that they can
+    // actually make packets for. Those keys are public, so an image built with
+    // them would decode for anyone, and they never go on the board.
+    println!("cargo::rerun-if-env-changed=DECODER_TEST_SECRETS");
+    if !secrets_path.exists() {
+        let opted_in = var("DECODER_TEST_SECRETS").is_ok_and(|opt_in| opt_in == "1");
+        assert!(
+            opted_in,
+            "{} does not exist. Set GLOBAL_SECRETS to the deployment's secrets, or \
+             DECODER_TEST_SECRETS=1 to build for the host with the test secrets.",
+            secrets_path.display()
+        );
+        let target = var("TARGET").unwrap();
+        assert!(
+            !target.starts_with("thumb"),
+            "the test secrets are public, so they can't be built in for the board ({target})"
+        );
+
+        println!("cargo::warning=secrets file does not exist, using test secrets.");
+        secrets_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test.secrets");
+        decoder_id = decoder_id.or(Ok("0xdeadbeef".to_string()));
+        test_secrets = true;
+    }
+
+    let decoder_id = decoder_id.expect("DECODER_ID env var was not present");
+
+    // Let the tests know which deployment they're working with.
+    println!(
+        "cargo::rustc-env=DECODER_SECRETS_PATH={}",
+        secrets_path.display()
+    );
+    println!("cargo::rustc-env=DECODER_ID={decoder_id}");
+
+    // Import secrets
+    let secrets = fs::read(&secrets_path).expect("couldn't read secrets");
+    let secrets = Secrets::from_json(&secrets).expect("couldn't parse secrets");
+
+    // ChaCha20 (symmetric/encrypting) secrets
+    // The decoder key derivation is shared with the host tools, so that
+    // subscriptions are always made for the key that we bake in here.
+    let decoder_id = parse_decoder_id(&decoder_id).expect("couldn't parse the decoder id");
+    let decoder_key = secrets.decoder_key(decoder_id);
+    let channel_0_key = secrets.channel_0_key;
+
+    let legacy_flash_key = legacy_flash_key().or(test_secrets.then_some(TEST_LEGACY_FLASH_KEY));
+    println!(
+        "cargo::rustc-env=DECODER_LEGACY_FLASH_KEY={}",
+        legacy_flash_key.map(hex::encode).unwrap_or_default()
+    );
+
+    // The flash key is derived the same way for every build of this decoder,
+    // so reflashing it doesn't lose what it has saved.
+    let flash_key = secrets.flash_key(decoder_id);
+
+    // Ed25519 (asymmetric/signing) secrets
+    // Note for the reader:
+    // sk -> secret key (this does not go to the decoder)
+    // vk -> verifying key (this goes to the decoder)
+    // The decoder gets every key that the deployment might sign with, by the
+    // key ID that crypto headers name them with.
+    let verifying_keys = secrets.accepted_verifying_keys();
+
+    for (key_id, key) in &verifying_keys {
+        let signing_vk = VerifyingKey::from_bytes(key).expect("secrets checked the key");
+        assert!(!signing_vk.is_weak(), "How is signing key {key_id} weak?");
+    }
+
+    let verifying_keys: Vec<_> = verifying_keys.into_iter().collect();
+
+    fs::write(
+        out.join("gen_constants.rs"),
+        format!(
+            "const DECODER_KEY: Chacha20Key = {:#?};\npub(crate) const CHANNEL_0_KEY: Chacha20Key = {:#?};\nconst VERIFYING_KEYS_COMPRESSED: [(u8, Ed25519PubKey); {}] = {:#?};\nconst FLASH_KEY: Chacha20Key = {:#?};\nconst LEGACY_FLASH_KEY: Option<Chacha20Key> = {:#?};",
+            decoder_key, channel_0_key, verifying_keys.len(), verifying_keys, flash_key, legacy_flash_key
+        ),
+    )
+    .expect("Failed to write constants");
+
+    let firmware_version = ["MAJOR", "MINOR", "PATCH"]
+        .iter()
+        .map(|part| {
+            var(format!("CARGO_PKG_VERSION_{part}"))
+                .unwrap()
+                .parse::<u8>()
+                .expect("version parts should fit in a byte")
+        })
+        .fold(0u32, |version, part| (version << 8) | part as u32);
+
+    println!("cargo::rerun-if-env-changed=MAX_SUBSCRIPTIONS");
+    let max_subscriptions: usize = var("MAX_SUBSCRIPTIONS")
+        .map(|max| max.parse().expect("MAX_SUBSCRIPTIONS should be a number"))
+        .unwrap_or(8);
+    assert!(
+        max_subscriptions > 0,
+        "the decoder needs room for a subscription"
+    );
+
+    println!("cargo::rerun-if-env-changed=TIMESTAMPS_PER_SECOND");
+    let timestamps_per_second: u64 = var("TIMESTAMPS_PER_SECOND")
+        .map(|rate| {
+            rate.parse()
+                .expect("TIMESTAMPS_PER_SECOND should be a number")
+        })
+        .unwrap_or(1_000_000);
+    assert!(
+        timestamps_per_second > 0,
+        "timestamps have to count up at least once a second"
+    );
+
+    fs::write(
+        out.join("gen_config.rs"),
+        format!(
+            "pub const MAX_SUBSCRIPTION_COUNT: usize = {max_subscriptions};\npub const TIMESTAMPS_PER_SECOND: u64 = {timestamps_per_second};"
+        ),
+    )
+    .expect("Failed to write config");
+
+    fs::write(
+        out.join("gen_info.rs"),
+        format!(
+            "pub const DECODER_ID: u32 = {decoder_id:#x};\npub const FIRMWARE_VERSION: u32 = {firmware_version:#x};\npub const BUILD_HASH: [u8; BUILD_HASH_LEN] = {:?};",
+            build_hash()
+        ),
+    )
+    .expect("Failed to write build info");
+}
+
+/// The flash key that the first release sealed its storage with, if this
+/// build is to read that storage.
+///
+/// That firmware made up its flash key at random for every build and never
+/// saved it anywhere but the `gen_constants.rs` it generated, so it can't be
+/// derived from the secrets like the other keys. It has to be given in hex as
+/// `LEGACY_FLASH_KEY`. Without it, the old storage is wiped.
+fn legacy_flash_key() -> Option<[u8; 32]> {
+    println!("cargo::rerun-if-env-changed=LEGACY_FLASH_KEY");
+    let key = var("LEGACY_FLASH_KEY").ok()?;
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(key.trim(), &mut bytes)
+        .expect("LEGACY_FLASH_KEY should be 32 bytes of hex");
+    Some(bytes)
+}
+
+/// The commit that we're building, from `BUILD_HASH` if it's set (the docker
+/// build doesn't have the repository's history), otherwise from git. All zeros
+/// if neither knows.
+fn build_hash() -> [u8; 20] {
+    let hash = var("BUILD_HASH").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    });
+
+    hash.and_then(|hash| hex::decode(hash.trim()).ok())
+        .and_then(|hash| hash.try_into().ok())
+        .unwrap_or_else(|| {
+            println!("cargo::warning=couldn't find the commit hash, reporting all zeros.");
+            [0; 20]
+        })
+}
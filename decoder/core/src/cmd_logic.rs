@@ -0,0 +1,120 @@
+use decoder_protocol::{MessageHeader, Opcode};
+use embedded_io::{Read, Write};
+
+use crate::{
+    crypto::EntropySource,
+    decoder::Decoder,
+    flash::FlashBackend,
+    host_comms::{
+        parse_key_rotation, parse_subscription, parse_unsubscription, DecoderConsole, DecoderError,
+    },
+    info,
+    led::StatusLed,
+    timer::{Clock, FAILED_DECRYPTION_DELAY_MS},
+};
+
+pub fn run_command<T: Read + Write, F: FlashBackend, E: EntropySource>(
+    console: &mut DecoderConsole<T>,
+    decoder: &mut Decoder<F, E>,
+    led: &mut impl StatusLed,
+    clock: &mut impl Clock,
+) -> Result<(), DecoderError> {
+    let hdr = console.read_command_header()?;
+    // We read the header, transaction time starts now.
+    let started_ms = clock.now_ms();
+
+    let result = handle_command(hdr, console, decoder, led);
+
+    // Whatever it was that didn't check out, the host doesn't get to hear
+    // about it until the delay is up.
+    if result
+        .as_ref()
+        .is_err_and(DecoderError::is_authentication_failure)
+    {
+        clock.wait_until(started_ms + FAILED_DECRYPTION_DELAY_MS);
+    }
+
+    result
+}
+
+/// Answers every command with `err`, for when the decoder can't start. Each
+/// command's body is still taken off the wire, so that the next one is found.
+pub fn refuse_commands<T: Read + Write>(console: &mut DecoderConsole<T>, err: DecoderError) -> ! {
+    loop {
+        if let Ok(hdr) = console.read_command_header() {
+            let _ = console.read_command_body(hdr);
+        }
+        err.write_to_console(console);
+    }
+}
+
+fn handle_command<T: Read + Write, F: FlashBackend, E: EntropySource>(
+    hdr: MessageHeader,
+    console: &mut DecoderConsole<T>,
+    decoder: &mut Decoder<F, E>,
+    led: &mut impl StatusLed,
+) -> Result<(), DecoderError> {
+    match hdr.opcode {
+        Opcode::List => {
+            led.cyan();
+
+            // List subscriptions
+            // There's no body, but this still checks that there isn't one.
+            console.read_command_body(hdr)?;
+
+            let subscriptions = decoder.get_subscriptions().iter().flatten();
+            console.send_list(subscriptions)?;
+        }
+        Opcode::Info => {
+            led.cyan();
+
+            // Read only, like List.
+            console.read_command_body(hdr)?;
+
+            console.send_info(&info::collect(decoder))?;
+        }
+        Opcode::Subscribe => {
+            led.yellow();
+
+            let body = console.read_command_body(hdr)?;
+            let sub = parse_subscription(&body, decoder.verifying_keys())?;
+
+            let evicted = decoder.register_subscription(sub)?;
+            if !evicted.is_empty() {
+                console.print_evicted(&evicted)?;
+            }
+
+            console.send_empty_payload(Opcode::Subscribe)?;
+        }
+        Opcode::Unsubscribe => {
+            led.yellow();
+
+            let body = console.read_command_body(hdr)?;
+            let unsub = parse_unsubscription(&body, decoder.verifying_keys())?;
+
+            decoder.remove_subscription(unsub.channel_id, unsub.issued)?;
+
+            console.send_empty_payload(Opcode::Unsubscribe)?;
+        }
+        Opcode::RotateKey => {
+            led.yellow();
+
+            let body = console.read_command_body(hdr)?;
+            let rotation = parse_key_rotation(&body, decoder.verifying_keys())?;
+
+            decoder.rotate_key(rotation)?;
+
+            console.send_empty_payload(Opcode::RotateKey)?;
+        }
+        Opcode::Decode => {
+            led.magenta();
+
+            let body = console.read_command_body(hdr)?;
+            console.decode_frame(decoder, &body)?;
+        }
+        // read_command_header only lets commands through.
+        Opcode::Ack | Opcode::Debug | Opcode::Error => return Err(DecoderError::InvalidCommand),
+    };
+
+    Ok(())
+}
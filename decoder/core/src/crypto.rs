@@ -0,0 +1,270 @@
+use chacha20poly1305::{aead::AeadMutInPlace, KeyInit, XChaCha20Poly1305};
+use decoder_protocol::{CryptoHeader, KeyRotationBody};
+use ed25519_dalek::{Signature, VerifyingKey};
+use once_cell::sync::OnceCell;
+
+/// A source of random bytes, used for generating nonces.
+pub trait EntropySource {
+    fn fill_bytes(&mut self, dest: &mut [u8]);
+}
+
+impl<T: EntropySource> EntropySource for &mut T {
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        T::fill_bytes(self, dest)
+    }
+}
+
+// Encryption
+pub const CHACHA20_KEY_BYTES: usize = decoder_protocol::KEY_LEN;
+pub const XCHACHA20_NONCE_BYTES: usize = decoder_protocol::NONCE_LEN;
+pub const XCHACHA20_TAG_BYTES: usize = decoder_protocol::TAG_LEN;
+pub type Chacha20Key = [u8; CHACHA20_KEY_BYTES];
+pub type XChacha20Nonce = [u8; XCHACHA20_NONCE_BYTES];
+pub type XChacha20Tag = [u8; XCHACHA20_TAG_BYTES];
+
+// Signing
+pub const ED25519_SIGNATURE_BYTES: usize = decoder_protocol::SIGNATURE_LEN;
+pub type Ed25519PubKey = [u8; ed25519_dalek::PUBLIC_KEY_LENGTH];
+pub type Ed25519Signature = [u8; ED25519_SIGNATURE_BYTES];
+
+// Crypto Header
+pub const ENCODER_CRYPTO_HEADER_LEN: usize = CryptoHeader::SIZE;
+
+// The wire format and the crypto library had better agree on these.
+const _: () = assert!(ED25519_SIGNATURE_BYTES == ed25519_dalek::SIGNATURE_LENGTH);
+const _: () = assert!(
+    ENCODER_CRYPTO_HEADER_LEN
+        == 2 + XCHACHA20_NONCE_BYTES + XCHACHA20_TAG_BYTES + ED25519_SIGNATURE_BYTES
+);
+
+include!(concat!(env!("OUT_DIR"), "/gen_constants.rs"));
+
+/// Why an encrypted packet couldn't be opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptoError {
+    /// The crypto header names a suite that we don't accept.
+    UnknownSuite(u8),
+    /// The crypto header names a signing key that we don't have.
+    UnknownKey(u8),
+    /// The signature or the tag was wrong.
+    Failed,
+}
+
+/// The AEADs that a packet can be sealed with, named in its crypto header by
+/// [`AeadSuite::id`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeadSuite {
+    XChaCha20Poly1305,
+}
+
+// The host tools take the current suite from decoder-protocol.
+const _: () = assert!(AeadSuite::CURRENT.id() == decoder_protocol::CURRENT_SUITE);
+
+/// Every suite that this firmware can open. A deployment can only move to a
+/// new suite once the decoders in the field have it in here.
+const ACCEPTED_SUITES: &[AeadSuite] = &[AeadSuite::XChaCha20Poly1305];
+
+impl AeadSuite {
+    /// What the host tools seal new packets with.
+    pub const CURRENT: Self = Self::XChaCha20Poly1305;
+
+    pub const fn id(self) -> u8 {
+        match self {
+            Self::XChaCha20Poly1305 => decoder_protocol::SUITE_XCHACHA20_POLY1305,
+        }
+    }
+
+    /// Looks up the suite for an ID from a crypto header.
+    pub fn from_id(id: u8) -> Result<Self, CryptoError> {
+        ACCEPTED_SUITES
+            .iter()
+            .copied()
+            .find(|suite| suite.id() == id)
+            .ok_or(CryptoError::UnknownSuite(id))
+    }
+
+    fn decrypt(
+        self,
+        key: &Chacha20Key,
+        nonce: &XChacha20Nonce,
+        tag: &XChacha20Tag,
+        associated_data: &[u8],
+        body: &mut [u8],
+    ) -> Result<(), CryptoError> {
+        match self {
+            Self::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into())
+                .decrypt_in_place_detached(nonce.into(), associated_data, body, tag.into())
+                .or(Err(CryptoError::Failed)),
+        }
+    }
+}
+
+// Initializing the VerifyingKey object from a compressed byte array is
+// non-trivial, so I'd like to avoid doing it on every frame.
+fn built_in_keys() -> &'static [(u8, VerifyingKey); VERIFYING_KEYS_COMPRESSED.len()] {
+    static VERIFYING_KEYS: OnceCell<[(u8, VerifyingKey); VERIFYING_KEYS_COMPRESSED.len()]> =
+        OnceCell::new();
+
+    VERIFYING_KEYS.get_or_init(|| {
+        VERIFYING_KEYS_COMPRESSED.map(|(key_id, key)| {
+            let key = VerifyingKey::from_bytes(&key)
+                .expect("VERIFYING_KEYS_COMPRESSED are always valid Ed25519 public keys");
+            (key_id, key)
+        })
+    })
+}
+
+/// A satellite verifying key that a key rotation put in place.
+#[derive(Debug, Clone, Copy)]
+struct RotatedKey {
+    counter: u32,
+    key_id: u8,
+    key: VerifyingKey,
+}
+
+/// The keys that signatures are checked with. Until the satellite key is
+/// rotated, these are the ones that the firmware was built with. After that,
+/// only the key from the last rotation is accepted, so that a retired key
+/// can't sign anything ever again.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VerifyingKeys {
+    rotated: Option<RotatedKey>,
+}
+
+impl VerifyingKeys {
+    /// The keys after `rotation`, or the built-in ones if there hasn't been
+    /// one. Returns `None` if the rotation's key isn't one that we could check
+    /// signatures with.
+    pub fn new(rotation: Option<&KeyRotationBody>) -> Option<Self> {
+        let Some(rotation) = rotation else {
+            return Some(Self::default());
+        };
+
+        let key = VerifyingKey::from_bytes(&rotation.verifying_key).ok()?;
+        if key.is_weak() {
+            return None;
+        }
+
+        Some(Self {
+            rotated: Some(RotatedKey {
+                counter: rotation.counter,
+                key_id: rotation.key_id,
+                key,
+            }),
+        })
+    }
+
+    /// The counter of the last rotation, or 0 if there hasn't been one.
+    pub fn rotation_counter(&self) -> u32 {
+        self.rotated.map_or(0, |rotated| rotated.counter)
+    }
+
+    /// The key to check a signature with, by the key ID from a crypto header.
+    pub fn get_verifying_key(&self, key_id: u8) -> Result<&VerifyingKey, CryptoError> {
+        let key = match &self.rotated {
+            Some(rotated) => (rotated.key_id == key_id).then_some(&rotated.key),
+            None => built_in_keys()
+                .iter()
+                .find(|(id, _)| *id == key_id)
+                .map(|(_, key)| key),
+        };
+
+        key.ok_or(CryptoError::UnknownKey(key_id))
+    }
+
+    fn verify(
+        &self,
+        key_id: u8,
+        message: &[u8],
+        signature: &Ed25519Signature,
+    ) -> Result<(), CryptoError> {
+        self.get_verifying_key(key_id)?
+            .verify_strict(message, &Signature::from_bytes(signature))
+            .or(Err(CryptoError::Failed))
+    }
+}
+
+/// Allows main to bootstrap the OnceCell in crypto without needing to let the
+/// implementation details of it leaking.
+pub fn bootstrap_crypto() {
+    let _ = built_in_keys();
+}
+
+/// Checks the signature over `signed` against `keys`, and only then decrypts
+/// `body` in place, so that a forged packet is turned away without running the
+/// cipher. `signed` has to cover the associated data, the ciphertext in `body`
+/// and the tag.
+pub fn verify_then_decrypt(
+    key: &Chacha20Key,
+    keys: &VerifyingKeys,
+    crypto: &CryptoHeader,
+    signed: &[u8],
+    associated_data: &[u8],
+    body: &mut [u8],
+) -> Result<(), CryptoError> {
+    let suite = AeadSuite::from_id(crypto.suite)?;
+
+    keys.verify(crypto.key_id, signed, &crypto.signature)?;
+
+    suite.decrypt(key, &crypto.nonce, &crypto.tag, associated_data, body)
+}
+
+/// [`verify_then_decrypt`] with the decoder key, for the updates that are
+/// sealed for this decoder alone.
+pub fn verify_then_decrypt_for_decoder(
+    keys: &VerifyingKeys,
+    crypto: &CryptoHeader,
+    signed: &[u8],
+    associated_data: &[u8],
+    body: &mut [u8],
+) -> Result<(), CryptoError> {
+    verify_then_decrypt(&DECODER_KEY, keys, crypto, signed, associated_data, body)
+}
+
+/// Encrypts the flash buffer.
+///
+/// Returns a tuple of the nonce and the tag
+pub fn encrypt_flash_buffer(
+    buffer: &mut [u8],
+    associated_data: &[u8],
+    entropy: &mut impl EntropySource,
+) -> Result<(XChacha20Nonce, XChacha20Tag), ()> {
+    let mut cipher = XChaCha20Poly1305::new((&FLASH_KEY).into());
+    let mut nonce: XChacha20Nonce = Default::default();
+    entropy.fill_bytes(&mut nonce);
+
+    match cipher.encrypt_in_place_detached(&nonce.into(), associated_data, buffer) {
+        Ok(tag) => Ok((nonce, tag.into())),
+        Err(_) => Err(()),
+    }
+}
+
+// Decrypts the flash buffer
+pub fn decrypt_flash_buffer(
+    buffer: &mut [u8],
+    associated_data: &[u8],
+    nonce: &XChacha20Nonce,
+    tag: &XChacha20Tag,
+) -> Result<(), ()> {
+    let mut cipher = XChaCha20Poly1305::new((&FLASH_KEY).into());
+
+    cipher
+        .decrypt_in_place_detached(nonce.into(), associated_data, buffer, tag.into())
+        .or(Err(()))
+}
+
+/// Decrypts what the firmware from before the storage log saved, which was
+/// sealed with a flash key that its build made up at random. That key only
+/// gets here if it was given to the build as `LEGACY_FLASH_KEY`.
+pub fn decrypt_legacy_flash_buffer(
+    buffer: &mut [u8],
+    nonce: &XChacha20Nonce,
+    tag: &XChacha20Tag,
+) -> Result<(), ()> {
+    let key = LEGACY_FLASH_KEY.ok_or(())?;
+    let mut cipher = XChaCha20Poly1305::new((&key).into());
+
+    cipher
+        .decrypt_in_place_detached(nonce.into(), &[], buffer, tag.into())
+        .or(Err(()))
+}
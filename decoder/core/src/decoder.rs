@@ -0,0 +1,479 @@
+use decoder_protocol::{DecodeRequestHeader, KeyRotationBody, MAX_FRAME_LEN};
+use postcard::to_extend;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::{
+    crypto::{verify_then_decrypt, Chacha20Key, EntropySource, VerifyingKeys, CHANNEL_0_KEY},
+    flash::{DecoderStorage, FlashBackend},
+    host_comms::DecoderError,
+};
+
+mod migrate;
+
+// How many subscriptions the decoder has room for, and how many frame
+// timestamps make up a second, set at build time by the `MAX_SUBSCRIPTIONS`
+// and `TIMESTAMPS_PER_SECOND` environment variables.
+include!(concat!(env!("OUT_DIR"), "/gen_config.rs"));
+
+/// The most that one saved subscription can take up. Postcard writes integers
+/// as varints, which are at most 5 bytes for a u32 and 10 for a u64.
+const SAVED_SUBSCRIPTION_MAX: usize = 5 + 10 + 10 + 32 + 10;
+
+/// The most that one saved [`Change`] can take up: the channels it removes,
+/// the issue time floor, and the subscription it adds.
+pub const RECORD_MAX: usize = 10 + MAX_SUBSCRIPTION_COUNT * 5 + 10 + 1 + SAVED_SUBSCRIPTION_MAX;
+
+/// The most that each change in a snapshot of the saved state can take up.
+/// These never remove any channels.
+pub const SNAPSHOT_RECORD_MAX: usize = 1 + 10 + 1 + SAVED_SUBSCRIPTION_MAX;
+
+/// How many changes a snapshot of the saved state takes: one for the issue
+/// time floor, and one for each subscription.
+pub const SNAPSHOT_RECORDS: usize = 1 + MAX_SUBSCRIPTION_COUNT;
+
+/// The channels whose subscriptions were thrown out to make room for a new
+/// one.
+pub type EvictedChannels = heapless::Vec<u32, MAX_SUBSCRIPTION_COUNT>;
+
+/// How many seconds of frames each saved time bound covers.
+const TIME_BOUND_SECONDS: u64 = 10;
+
+/// How far past a frame's timestamp the saved bound is put. A bound is only
+/// written when a frame gets past the last one, so this is how many timestamps
+/// each flash write covers, and also at most how many are refused after a
+/// reboot. It is counted in the deployment's own timestamp unit, so that it is
+/// always ten seconds of frames.
+pub const TIME_BOUND_STRIDE: u64 = TIME_BOUND_SECONDS * TIMESTAMPS_PER_SECOND;
+
+/// This struct represents the concept of the decoder. It will decode frames
+/// that it has a valid subscription for, and can register more subscriptions.
+pub struct Decoder<'a, F, E> {
+    subscriptions: [Option<Subscription>; MAX_SUBSCRIPTION_COUNT],
+    /// Updates issued at or before this are refused for every channel. This
+    /// goes up whenever the decoder forgets a channel's subscription, so that
+    /// forgetting it doesn't let an older update for it back in. There's one
+    /// floor rather than one per forgotten channel, so it holds back older
+    /// updates for the other channels too (see [`Self::remove_subscription`]).
+    issued_floor: u64,
+    storage: &'a mut DecoderStorage<F, E>,
+    curr_time: Option<u64>,
+    /// What signatures are checked with. This is loaded from the last key
+    /// rotation that was saved, if there has been one.
+    verifying_keys: VerifyingKeys,
+}
+
+impl<'a, F: FlashBackend, E: EntropySource> Decoder<'a, F, E> {
+    /// Loads the decoder's state from `storage`. Fails if the saved key
+    /// rotation's key can't be used, since going back to the built-in key
+    /// would let the rotations before it be sent again.
+    pub fn new(storage: &'a mut DecoderStorage<F, E>) -> Result<Self, DecoderError> {
+        let mut subscriptions = core::array::from_fn(|_| None);
+        let mut issued_floor = 0;
+
+        let version = storage.version();
+        storage.replay(|record| {
+            // A change that doesn't deserialize is skipped, the same as one
+            // that doesn't decrypt.
+            migrate::decode_changes(version, record, |change| {
+                change.apply(&mut subscriptions, &mut issued_floor)
+            });
+        });
+
+        // Nothing up to the saved bound can be decoded, since we might have
+        // decoded it before the reboot.
+        let curr_time = storage.time_bound();
+
+        // Rotations are checked before they are saved, so this is a key log
+        // that has been damaged since.
+        let verifying_keys =
+            VerifyingKeys::new(storage.key_rotation()).ok_or(DecoderError::LoadingFailed)?;
+
+        Ok(Self {
+            subscriptions,
+            issued_floor,
+            storage,
+            curr_time,
+            verifying_keys,
+        })
+    }
+
+    pub fn get_subscriptions(&self) -> &[Option<Subscription>] {
+        &self.subscriptions
+    }
+
+    /// Adds a subscription, replacing any that there already is for the
+    /// channel. If every slot is taken, expired subscriptions are evicted to
+    /// make room, and the channels they were for are returned.
+    pub fn register_subscription(
+        &mut self,
+        new_sub: Subscription,
+    ) -> Result<EvictedChannels, DecoderError> {
+        self.check_issued(new_sub.channel_id, new_sub.issued)?;
+
+        let mut change = Change {
+            removed: EvictedChannels::new(),
+            issued_floor: self.issued_floor,
+            added: None,
+        };
+
+        // A subscription for a channel that's already in the list replaces
+        // that one, otherwise it needs a free slot.
+        let replacing = self.get_subscription(new_sub.channel_id).is_some();
+        if !replacing && self.subscriptions.iter().all(|s| s.is_some()) {
+            (change.removed, change.issued_floor) = self.expired();
+            if change.removed.is_empty() {
+                return Err(DecoderError::NoMoreSubscriptionSpace);
+            }
+        }
+
+        change.added = Some(new_sub);
+        let evicted = change.removed.clone();
+        self.commit(change)?;
+
+        Ok(evicted)
+    }
+
+    /// Finds every subscription that ended at or before the last timestamp,
+    /// since frames have to be newer than that, so they can't decode anything
+    /// ever again. Returns their channels, and what the issue time floor has
+    /// to be once they're gone.
+    fn expired(&self) -> (EvictedChannels, u64) {
+        let mut expired = EvictedChannels::new();
+        let mut issued_floor = self.issued_floor;
+        let Some(curr_time) = self.curr_time else {
+            return (expired, issued_floor);
+        };
+
+        for sub in self
+            .subscriptions
+            .iter()
+            .flatten()
+            .filter(|sub| sub.end_time <= curr_time)
+        {
+            // Like a removal, the floor has to cover the channel so that an
+            // older update for it can't come back in.
+            issued_floor = issued_floor.max(sub.issued);
+            expired
+                .push(sub.channel_id)
+                .expect("there are only MAX_SUBSCRIPTION_COUNT slots");
+        }
+
+        (expired, issued_floor)
+    }
+
+    /// Removes the subscription for a channel, freeing up its slot. `issued` is
+    /// when the unsubscribe update was made, and has to be newer than the
+    /// subscription it removes.
+    ///
+    /// This raises the issue time floor to `issued` for every channel, not
+    /// just this one, so any update that was made before the unsubscribe is
+    /// refused afterwards, even for another channel. Updates have to be sent
+    /// in the order they were made, and ones made before an unsubscribe have
+    /// to be made again after it.
+    pub fn remove_subscription(
+        &mut self,
+        channel_id: u32,
+        issued: u64,
+    ) -> Result<(), DecoderError> {
+        self.check_issued(channel_id, issued)?;
+
+        if self.get_subscription(channel_id).is_none() {
+            return Err(DecoderError::NoSubscription);
+        }
+
+        let mut removed = EvictedChannels::new();
+        removed
+            .push(channel_id)
+            .expect("there are only MAX_SUBSCRIPTION_COUNT slots");
+
+        // Nothing remembers the channel once it's gone, so the floor has to
+        // cover it.
+        self.commit(Change {
+            removed,
+            issued_floor: issued,
+            added: None,
+        })
+    }
+
+    /// Refuses an update for a channel unless it was issued after the one we
+    /// already have for it, and after anything we've forgotten. The floor is
+    /// shared by every channel, so this refuses an update made before any
+    /// removal or eviction, whichever channel it was for.
+    fn check_issued(&self, channel_id: u32, issued: u64) -> Result<(), DecoderError> {
+        let newest = self
+            .get_subscription(channel_id)
+            .map_or(self.issued_floor, |sub| sub.issued.max(self.issued_floor));
+
+        if issued <= newest {
+            return Err(DecoderError::StaleUpdate);
+        }
+
+        Ok(())
+    }
+
+    /// The keys that updates and frames have to be signed with.
+    pub fn verifying_keys(&self) -> &VerifyingKeys {
+        &self.verifying_keys
+    }
+
+    /// Replaces the satellite verifying key with the one from a rotation,
+    /// which has already had its signature checked. The rotation has to have
+    /// a higher counter than the last one, so that an older key can't be put
+    /// back. It is saved before it takes effect.
+    pub fn rotate_key(&mut self, rotation: KeyRotationBody) -> Result<(), DecoderError> {
+        if rotation.counter <= self.verifying_keys.rotation_counter() {
+            return Err(DecoderError::StaleKeyRotation);
+        }
+
+        let verifying_keys =
+            VerifyingKeys::new(Some(&rotation)).ok_or(DecoderError::BadVerifyingKey)?;
+
+        self.storage.record_key_rotation(&rotation)?;
+        self.verifying_keys = verifying_keys;
+
+        Ok(())
+    }
+
+    /// The timestamp that frames have to be newer than, if there is one yet.
+    pub fn last_timestamp(&self) -> Option<u64> {
+        self.curr_time
+    }
+
+    /// The storage version that the subscriptions were saved with.
+    pub fn storage_version(&self) -> u32 {
+        self.storage.version()
+    }
+
+    /// How many bytes of storage the saved subscriptions take up.
+    pub fn storage_used(&self) -> u32 {
+        self.storage.stored_len()
+    }
+
+    /// Get the subscription for a given channel_id, if there is any.
+    pub fn get_subscription(&self, channel_id: u32) -> Option<&Subscription> {
+        self.subscriptions
+            .iter()
+            .flatten()
+            .find(|s| s.channel_id == channel_id)
+    }
+
+    /// Saves a change, and only then makes it to the subscriptions, so that
+    /// the decoder is left as it was if the change can't be saved. If the log
+    /// is full, it is compacted instead, which saves the change along with
+    /// everything else.
+    fn commit(&mut self, change: Change<Subscription>) -> Result<(), DecoderError> {
+        let saved = Change {
+            removed: change.removed.clone(),
+            issued_floor: change.issued_floor,
+            added: change.added.as_ref(),
+        };
+        serialize_change(self.storage.get_buf_mut(), &saved)?;
+
+        if self.storage.append_buffer()? {
+            change.apply(&mut self.subscriptions, &mut self.issued_floor);
+            return Ok(());
+        }
+
+        let mut subscriptions = self.subscriptions.clone();
+        let mut issued_floor = self.issued_floor;
+        change.apply(&mut subscriptions, &mut issued_floor);
+
+        let compacted = self.compact(&subscriptions, issued_floor);
+        if compacted.is_ok() {
+            core::mem::swap(&mut self.subscriptions, &mut subscriptions);
+            self.issued_floor = issued_floor;
+        }
+
+        // Don't leave the channel keys of whichever copy is unused lying
+        // around in RAM.
+        for sub in subscriptions.iter_mut().flatten() {
+            sub.channel_key.zeroize();
+        }
+
+        compacted
+    }
+
+    /// Starts the log over in the other bank, with a change for the issue
+    /// time floor and one for each subscription.
+    fn compact(
+        &mut self,
+        subscriptions: &[Option<Subscription>],
+        issued_floor: u64,
+    ) -> Result<(), DecoderError> {
+        self.storage.begin_compaction()?;
+
+        let floor = Change {
+            removed: EvictedChannels::new(),
+            issued_floor,
+            added: None,
+        };
+        let subscriptions = subscriptions.iter().flatten().map(|sub| Change {
+            removed: EvictedChannels::new(),
+            issued_floor,
+            added: Some(sub),
+        });
+
+        for change in core::iter::once(floor).chain(subscriptions) {
+            serialize_change(self.storage.get_buf_mut(), &change)?;
+
+            // The banks are sized so that a snapshot always fits in an empty
+            // one.
+            if !self.storage.append_buffer()? {
+                return Err(DecoderError::SavingFailed);
+            }
+        }
+
+        self.storage.finish_compaction()?;
+
+        Ok(())
+    }
+
+    /// Checks a frame's channel and timestamp, then its signature, then
+    /// decrypts it given the channel id and crypto parameters. The timestamp
+    /// is sent in the clear, so a frame that would be refused anyway is turned
+    /// away before any crypto. payload is decrypted in place.
+    pub fn decode_frame<'p>(
+        &mut self,
+        header: &DecodeRequestHeader,
+        payload: &'p mut heapless::Vec<u8, MAX_FRAME_LEN>,
+    ) -> Result<&'p [u8], DecoderError> {
+        let DecodeRequestHeader {
+            channel_id,
+            timestamp,
+            ref crypto,
+        } = *header;
+
+        let start_time;
+        let end_time;
+        let channel_key;
+
+        if channel_id == 0 {
+            start_time = u64::MIN;
+            end_time = u64::MAX;
+            channel_key = &CHANNEL_0_KEY
+        } else {
+            match self.get_subscription(channel_id) {
+                Some(sub) => {
+                    start_time = sub.start_time;
+                    end_time = sub.end_time;
+                    channel_key = &sub.channel_key;
+                }
+                None => return Err(DecoderError::NoSubscription),
+            };
+        };
+
+        if timestamp < start_time || timestamp > end_time {
+            return Err(DecoderError::SubscriptionTimeMismatch);
+        }
+
+        if let Some(curr_time) = self.curr_time {
+            if curr_time >= timestamp {
+                return Err(DecoderError::FrameOutOfOrder);
+            }
+        }
+
+        let mut signed = [0; DecodeRequestHeader::SIGNED_MAX];
+        let signed = header.signed_message(payload, &mut signed)?;
+
+        verify_then_decrypt(
+            channel_key,
+            &self.verifying_keys,
+            crypto,
+            signed,
+            &header.associated_data(),
+            payload,
+        )?;
+
+        // The frame has to be covered by the saved bound before it goes out,
+        // otherwise it could be replayed after a reboot.
+        if self
+            .storage
+            .time_bound()
+            .is_none_or(|bound| timestamp > bound)
+        {
+            self.storage
+                .record_time_bound(timestamp.saturating_add(TIME_BOUND_STRIDE))?;
+        }
+
+        self.curr_time = Some(timestamp);
+
+        Ok(payload)
+    }
+}
+
+/// One change to the subscriptions, which is saved as a record of its own.
+/// Replaying every change in the log, in order, gets back to where the
+/// decoder was.
+#[derive(Serialize, Deserialize)]
+struct Change<S> {
+    /// The channels whose subscriptions are gone.
+    removed: EvictedChannels,
+    /// The issue time floor after the change.
+    issued_floor: u64,
+    /// A subscription that was added, or that replaced the one for its
+    /// channel.
+    added: Option<S>,
+}
+
+impl Change<Subscription> {
+    fn apply(self, subscriptions: &mut [Option<Subscription>], issued_floor: &mut u64) {
+        for slot in subscriptions.iter_mut() {
+            if let Some(sub) = slot
+                .as_mut()
+                .filter(|sub| self.removed.contains(&sub.channel_id))
+            {
+                sub.channel_key.zeroize();
+                *slot = None;
+            }
+        }
+
+        *issued_floor = (*issued_floor).max(self.issued_floor);
+
+        let Some(added) = self.added else {
+            return;
+        };
+        let index = subscriptions
+            .iter()
+            .position(|s| s.as_ref().is_some_and(|s| s.channel_id == added.channel_id))
+            .or_else(|| subscriptions.iter().position(|s| s.is_none()));
+        if let Some(index) = index {
+            subscriptions[index] = Some(added);
+        }
+    }
+}
+
+fn serialize_change(
+    buf: &mut heapless::Vec<u8, RECORD_MAX>,
+    change: &Change<&Subscription>,
+) -> Result<(), DecoderError> {
+    buf.clear();
+    let buf = ExtendableHeaplessVecMut { the_reference: buf };
+    match to_extend(change, buf) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(DecoderError::SerializationFailed),
+    }
+}
+
+/// This helper type exists solely so that we can have Extend on a &mut to
+/// a heapless vec. Sigh.
+struct ExtendableHeaplessVecMut<'why, T, const N: usize> {
+    the_reference: &'why mut heapless::Vec<T, N>,
+}
+
+impl<T, const N: usize> Extend<T> for ExtendableHeaplessVecMut<'_, T, N> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.the_reference.extend(iter)
+    }
+}
+
+// Not Copy because it's potentially a bit big.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Subscription {
+    pub channel_id: u32,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub channel_key: Chacha20Key,
+    /// When the update that this came from was issued.
+    pub issued: u64,
+}
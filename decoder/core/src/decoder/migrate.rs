@@ -0,0 +1,66 @@
+//! Reading changes that older firmware saved.
+//!
+//! Each record is read with the schema of the storage version that saved it,
+//! and turned into [`Change`]s as they are now. Compacting the log writes it
+//! back out in the current version, so a migration only runs until the first
+//! change after an upgrade.
+
+use postcard::from_bytes;
+use serde::Deserialize;
+
+use super::{Change, EvictedChannels, Subscription};
+use crate::{
+    crypto::Chacha20Key,
+    flash::{LEGACY_VERSION, STORAGE_FORMAT_VERSION},
+};
+
+/// Decodes the changes in a record saved by storage version `version`, and
+/// hands them to `apply`. Nothing is handed over if the record doesn't decode,
+/// or if it's from a version that we can't read.
+pub(super) fn decode_changes(
+    version: u32,
+    record: &[u8],
+    mut apply: impl FnMut(Change<Subscription>),
+) {
+    match version {
+        STORAGE_FORMAT_VERSION => {
+            if let Ok(change) = from_bytes(record) {
+                apply(change);
+            }
+        }
+        LEGACY_VERSION => from_version_1(record, apply),
+        _ => {}
+    }
+}
+
+/// A subscription as version 1 saved it, before updates had an issue time.
+#[derive(Deserialize)]
+struct SubscriptionV1 {
+    channel_id: u32,
+    start_time: u64,
+    end_time: u64,
+    channel_key: Chacha20Key,
+}
+
+/// Version 1 saved every slot of a table that always had 8. Each subscription
+/// becomes a change of its own, issued at 0, so that any update made since
+/// replaces it.
+fn from_version_1(record: &[u8], mut apply: impl FnMut(Change<Subscription>)) {
+    let Ok(table) = from_bytes::<[Option<SubscriptionV1>; 8]>(record) else {
+        return;
+    };
+
+    for sub in table.into_iter().flatten() {
+        apply(Change {
+            removed: EvictedChannels::new(),
+            issued_floor: 0,
+            added: Some(Subscription {
+                channel_id: sub.channel_id,
+                start_time: sub.start_time,
+                end_time: sub.end_time,
+                channel_key: sub.channel_key,
+                issued: 0,
+            }),
+        });
+    }
+}
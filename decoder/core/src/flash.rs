@@ -0,0 +1,728 @@
+use decoder_protocol::KeyRotationBody;
+use zeroize::Zeroize;
+
+use crate::{
+    crypto::{
+        decrypt_flash_buffer, encrypt_flash_buffer, EntropySource, XChacha20Nonce, XChacha20Tag,
+    },
+    decoder::{RECORD_MAX, SNAPSHOT_RECORDS, SNAPSHOT_RECORD_MAX},
+    host_comms::DecoderError,
+};
+
+use core::fmt::Debug;
+
+use key_log::{KeyLog, KEY_LOG_PAGES};
+use legacy::LEGACY_MAX;
+use time_log::{TimeLog, TIME_LOG_PAGES};
+
+pub use legacy::LEGACY_VERSION;
+
+mod key_log;
+mod legacy;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+mod time_log;
+
+/// The version of the storage layout in docs/5-storage.md, which is saved in
+/// each bank's header. Logs saved by older versions are migrated, and ones
+/// saved by newer versions are left alone.
+pub const STORAGE_FORMAT_VERSION: u32 = 2;
+
+/// The size of a page of flash on the MAX78000.
+pub const FLASH_PAGE_SIZE: u32 = 0x2000;
+
+// Each bank starts with two 128-bit blocks of header: the magic, the storage
+// version, the generation and its complement, and then the commit word. The
+// commit word is written last, once everything that compaction copies into
+// the bank is there. The magic and version will always be the first two
+// words, whatever else changes.
+const BANK_HEADER_LEN: usize = 16 * 2;
+const COMMIT_OFFSET: u32 = 16;
+const COMMITTED: u32 = 0;
+
+// Each record starts with 3 128-bit blocks of header,
+// the ciphertext length and the first 12 bytes of the nonce,
+// then the rest of the nonce and the first 4 bytes of the MAC tag,
+// then the rest of the tag and 4 bytes of padding.
+// The ciphertext follows, padded out to a whole block.
+const RECORD_HEADER_LEN: usize = 16 * 3;
+
+const ERASED: u32 = 0xFFFFFFFF;
+
+const FLASH_INITIALIZED_MAGIC: u32 = 0x4d696b76;
+
+/// How much of a bank a record with `len` bytes of data takes up.
+const fn record_size(len: usize) -> usize {
+    RECORD_HEADER_LEN + len.next_multiple_of(16)
+}
+
+/// The most that compacting the log can write: the bank header, and every
+/// record of a snapshot at its largest.
+const SNAPSHOT_SIZE: usize = BANK_HEADER_LEN + SNAPSHOT_RECORDS * record_size(SNAPSHOT_RECORD_MAX);
+
+/// How many pages each of the two banks takes up. A bank has room for two
+/// snapshots and a record more, so that there is always at least a snapshot's
+/// worth of space for new records after compacting. Each bank is one run of
+/// pages in a fixed place, so nothing needs a page directory to find them.
+pub const BANK_PAGES: usize =
+    (2 * SNAPSHOT_SIZE + record_size(RECORD_MAX)).div_ceil(FLASH_PAGE_SIZE as usize);
+
+/// How many bytes of flash the log has to work with, which is one bank.
+pub const STORAGE_MAX: usize = BANK_PAGES * FLASH_PAGE_SIZE as usize;
+pub const STORAGE_MAX_U32: u32 = STORAGE_MAX as u32;
+
+/// How many pages of flash the storage uses, starting at its base address:
+/// the two banks, then the timestamp log, then the key log.
+pub const STORAGE_PAGES: usize = 2 * BANK_PAGES + (TIME_LOG_PAGES + KEY_LOG_PAGES) as usize;
+
+/// Errors that a [`FlashBackend`] can give us. These are the same ones that the
+/// flash controller on the board can give.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FlashError {
+    /// The address is outside of the flash, or isn't aligned.
+    InvalidAddress,
+    /// The flash was busy or locked when we tried to write or erase it.
+    AccessViolation,
+    /// The write would need to flip a 0 bit back to a 1, which needs an erase.
+    NeedsErase,
+}
+
+/// Something that behaves like NOR flash: erasing a page sets it to all 1s,
+/// and writes can only ever clear bits.
+///
+/// All addresses are absolute. 32-bit accesses must be 4 byte aligned and
+/// 128-bit accesses must be 16 byte aligned.
+pub trait FlashBackend {
+    fn read_32(&self, address: u32) -> Result<u32, FlashError>;
+    fn read_128(&self, address: u32) -> Result<[u32; 4], FlashError>;
+    fn write_32(&mut self, address: u32, data: u32) -> Result<(), FlashError>;
+    fn write_128(&mut self, address: u32, data: &[u32; 4]) -> Result<(), FlashError>;
+
+    /// Erases the page containing `address`, setting it to all 0xFF.
+    ///
+    /// # Safety
+    /// The caller must make sure that the page isn't one that we are running
+    /// code from.
+    unsafe fn erase_page(&mut self, address: u32) -> Result<(), FlashError>;
+}
+
+impl<T: FlashBackend> FlashBackend for &mut T {
+    fn read_32(&self, address: u32) -> Result<u32, FlashError> {
+        T::read_32(self, address)
+    }
+
+    fn read_128(&self, address: u32) -> Result<[u32; 4], FlashError> {
+        T::read_128(self, address)
+    }
+
+    fn write_32(&mut self, address: u32, data: u32) -> Result<(), FlashError> {
+        T::write_32(self, address, data)
+    }
+
+    fn write_128(&mut self, address: u32, data: &[u32; 4]) -> Result<(), FlashError> {
+        T::write_128(self, address, data)
+    }
+
+    unsafe fn erase_page(&mut self, address: u32) -> Result<(), FlashError> {
+        T::erase_page(self, address)
+    }
+}
+
+#[derive(Debug)]
+pub enum DecoderStorageReadError {
+    /// The length value in flash is invalid,
+    FlashLengthTooLarge,
+    /// Got an error from the flash library.
+    /// This is probably a logic bug.
+    FlashError,
+}
+
+impl From<FlashError> for DecoderStorageReadError {
+    fn from(_: FlashError) -> Self {
+        Self::FlashError
+    }
+}
+
+#[derive(Debug)]
+pub enum DecoderStorageWriteError {
+    /// Got an error from the flash library.
+    /// This is probably a logic bug.
+    FlashError,
+    /// Got an error encrypting the flash
+    /// This is also probably a logic bug.
+    CryptoError,
+    /// The subscription log was saved by newer firmware, so it can't be
+    /// changed.
+    NewerVersion,
+}
+
+impl From<FlashError> for DecoderStorageWriteError {
+    fn from(_: FlashError) -> Self {
+        Self::FlashError
+    }
+}
+
+impl From<DecoderStorageReadError> for DecoderError {
+    fn from(_: DecoderStorageReadError) -> Self {
+        Self::LoadingFailed
+    }
+}
+
+impl From<DecoderStorageWriteError> for DecoderError {
+    fn from(err: DecoderStorageWriteError) -> Self {
+        match err {
+            DecoderStorageWriteError::NewerVersion => Self::StorageFromNewerFirmware,
+            _ => Self::SavingFailed,
+        }
+    }
+}
+
+/// What a bank's header says about it.
+#[derive(Debug)]
+struct BankHeader {
+    version: u32,
+    generation: u32,
+    /// The magic and version are ones that we wrote, and the generation
+    /// matches its complement.
+    intact: bool,
+    /// Compaction finished writing the bank.
+    committed: bool,
+}
+
+/// What [`DecoderStorage::init`] found in flash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootOutcome {
+    /// There was no log, or none that checked out, so the storage was reset.
+    Reset,
+    /// The log was loaded. If it was saved by an older version, its records
+    /// are migrated as they are read, and it is rewritten in the current
+    /// version the next time that it is compacted.
+    Loaded { version: u32 },
+    /// The log was saved by newer firmware, which might have laid out the
+    /// banks in a way that we don't know about. Nothing is read from them, and
+    /// nothing is written to them, so that the newer firmware can still pick
+    /// up where it left off. The timestamp and key logs have layouts of their
+    /// own, and are still used, so that frames can still be decoded.
+    NewerVersion(u32),
+}
+/// The subscription state, kept as a log of records in one of two banks of
+/// flash.
+///
+/// Every change is appended to the log as its own sealed record, so saving
+/// one doesn't erase or rewrite anything else. When the bank fills up, the
+/// caller compacts the log into the other bank, which takes over once it is
+/// committed. Boot replays the records in the newest committed bank.
+pub struct DecoderStorage<F, E> {
+    flash: F,
+    entropy: E,
+    base_addr: u32,
+    buf: heapless::Vec<u8, RECORD_MAX>,
+    outcome: BootOutcome,
+    /// Which bank the log is in.
+    bank: u32,
+    /// The storage version that the log was saved with.
+    version: u32,
+    /// The generation of that bank, which goes up with every compaction.
+    generation: u32,
+    /// How far into the bank the next record goes.
+    cursor: u32,
+    /// The log ended at a record that doesn't check out, most likely one that
+    /// was cut off partway through being written, so nothing more can go
+    /// after it until the log is compacted.
+    torn: bool,
+    time_log: TimeLog,
+    key_log: KeyLog,
+}
+
+/// When debugging, we don't want the entire formatted 1024 byte buffer to be
+/// sent over the (probably slow/memory constrained) protocol that we're using.
+impl<F, E> Debug for DecoderStorage<F, E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DecoderStorage").finish_non_exhaustive()
+    }
+}
+
+impl<F: FlashBackend, E: EntropySource> DecoderStorage<F, E> {
+    /// Set up the storage on the [`STORAGE_PAGES`] flash pages starting at
+    /// `base_addr`, and find the log that was previously saved there.
+    pub fn init(
+        flash: F,
+        entropy: E,
+        base_addr: u32,
+    ) -> Result<DecoderStorage<F, E>, DecoderStorageReadError> {
+        let time_log_addr = base_addr + 2 * BANK_PAGES as u32 * FLASH_PAGE_SIZE;
+        let time_log = TimeLog::scan(&flash, time_log_addr)?;
+        let key_log = KeyLog::scan(&flash, time_log_addr + TIME_LOG_PAGES * FLASH_PAGE_SIZE)?;
+        let mut storage = Self {
+            flash,
+            entropy,
+            base_addr,
+            buf: heapless::Vec::new(),
+            outcome: BootOutcome::Reset,
+            // With no bank to start from, resetting moves on to bank 0.
+            bank: 1,
+            version: STORAGE_FORMAT_VERSION,
+            generation: 0,
+            cursor: BANK_HEADER_LEN as u32,
+            torn: false,
+            time_log,
+            key_log,
+        };
+
+        let mut banks = heapless::Vec::<(u32, BankHeader), 2>::new();
+        for bank in 0..2 {
+            let header = storage.read_bank_header(bank)?;
+
+            // There's no telling what newer firmware has done with the
+            // banks, so both are left as they are.
+            if header.intact && header.version > STORAGE_FORMAT_VERSION {
+                storage.outcome = BootOutcome::NewerVersion(header.version);
+                storage.version = header.version;
+                storage.cursor = 0;
+                return Ok(storage);
+            }
+
+            if header.committed {
+                banks.push((bank, header)).expect("there are only 2 banks");
+            }
+        }
+
+        // The commit word is written last, and can't be taken back without
+        // erasing the bank, so a committed bank whose header doesn't check out
+        // was damaged afterwards. It can't just be passed over, since it may
+        // be the newer bank. Compaction always moves on to the generation
+        // after the other bank's, so if its log checks out under that, it is.
+        // Otherwise it is the older one, or it can't be read at all.
+        let intact_generation = banks
+            .iter()
+            .find(|(_, header)| header.intact)
+            .map(|(_, header)| header.generation);
+        let mut damaged = heapless::Vec::<u32, 2>::new();
+        for (bank, header) in banks.iter_mut().filter(|(_, header)| !header.intact) {
+            if let Some(generation) = intact_generation {
+                header.version = STORAGE_FORMAT_VERSION;
+                header.generation = generation.wrapping_add(1);
+                header.intact = storage.first_record_checks_out(*bank, header)?;
+            }
+            if !header.intact {
+                damaged.push(*bank).expect("there are only 2 banks");
+            }
+        }
+        banks.retain(|(_, header)| header.intact);
+        banks.sort_unstable_by(|(_, a), (_, b)| b.generation.cmp(&a.generation));
+
+        // Use the newest bank, even if its log doesn't check out all the way
+        // through. The older bank never saw the change that the newer one was
+        // compacted for, which may have been an unsubscribe, so going back to
+        // it could undo that.
+        if let Some((bank, header)) = banks.first() {
+            storage.bank = *bank;
+            storage.generation = header.generation;
+            storage.version = header.version;
+            storage.scan()?;
+            storage.outcome = BootOutcome::Loaded {
+                version: header.version,
+            };
+            return Ok(storage);
+        }
+
+        // Firmware from before the log kept the subscriptions in one blob
+        // where bank 0 is now. It is read as it is, and left there until the
+        // first change compacts it into bank 1. Once that is committed, the
+        // blob is out of date, even if bank 1 has since been damaged.
+        let mut blob = heapless::Vec::<u8, LEGACY_MAX>::new();
+        if damaged.is_empty() && legacy::read_blob(&storage.flash, base_addr, &mut blob)? {
+            blob.zeroize();
+            storage.bank = 0;
+            storage.version = LEGACY_VERSION;
+            storage.cursor = 0;
+            storage.outcome = BootOutcome::Loaded {
+                version: LEGACY_VERSION,
+            };
+            return Ok(storage);
+        }
+
+        // Either this is the first boot, or the only committed banks are too
+        // damaged to read, so there is nothing to recover. The damaged banks
+        // are erased, so that neither can be taken for a newer bank than the
+        // empty log later. Nothing that resetting does can fail other than the
+        // flash itself.
+        for bank in damaged {
+            storage.erase_page(storage.bank_addr(bank))?;
+        }
+        storage
+            .reset_storage()
+            .or(Err(DecoderStorageReadError::FlashError))?;
+
+        Ok(storage)
+    }
+
+    /// Reset the flash so that next time that we read state in, we get an empty
+    /// log.
+    pub fn reset_storage(&mut self) -> Result<(), DecoderStorageWriteError> {
+        self.begin_compaction()?;
+        self.finish_compaction()
+    }
+
+    /// Reads the header of bank `bank`, if it is one that we recognize.
+    fn read_bank_header(&self, bank: u32) -> Result<BankHeader, FlashError> {
+        let address = self.bank_addr(bank);
+        let [magic, version, generation, not_generation] = self.flash.read_128(address)?;
+
+        Ok(BankHeader {
+            version,
+            generation,
+            // Every version that has used this magic is this one or newer.
+            intact: magic == FLASH_INITIALIZED_MAGIC
+                && version >= STORAGE_FORMAT_VERSION
+                && generation == !not_generation,
+            // The old firmware's blob has part of its nonce where the commit
+            // word is. It is left for init to find.
+            committed: magic != legacy::MAGIC
+                && self.flash.read_32(address + COMMIT_OFFSET)? == COMMITTED,
+        })
+    }
+
+    /// Whether the first record in `bank` checks out as part of a log with
+    /// the version and generation in `header`.
+    fn first_record_checks_out(
+        &mut self,
+        bank: u32,
+        header: &BankHeader,
+    ) -> Result<bool, DecoderStorageReadError> {
+        self.bank = bank;
+        self.version = header.version;
+        self.generation = header.generation;
+
+        let first = BANK_HEADER_LEN as u32;
+        match self.next_record(first) {
+            Ok(Some(_)) => {}
+            Ok(None) | Err(DecoderStorageReadError::FlashLengthTooLarge) => return Ok(false),
+            Err(err) => return Err(err),
+        }
+
+        let authentic = self.read_record(first)?;
+        self.buf.zeroize();
+        self.buf.clear();
+        Ok(authentic)
+    }
+
+    /// Walks the log to find where it ends: at the first record that is
+    /// still erased, or that doesn't check out. A record that the power was
+    /// cut in the middle of is always the last, but one that was damaged
+    /// can have good records after it. Those are left out as well, since
+    /// the changes that they follow on from are missing, and the state from
+    /// before the damaged one is all that can be trusted.
+    fn scan(&mut self) -> Result<(), DecoderStorageReadError> {
+        self.cursor = BANK_HEADER_LEN as u32;
+        self.torn = false;
+
+        loop {
+            let next = match self.next_record(self.cursor) {
+                Ok(Some(next)) => next,
+                Ok(None) => return Ok(()),
+                // A length that runs off the end of the bank was never
+                // written by us, or was cut off while it was being written.
+                Err(DecoderStorageReadError::FlashLengthTooLarge) => {
+                    self.torn = true;
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            };
+            let authentic = self.read_record(self.cursor)?;
+            self.buf.zeroize();
+            self.buf.clear();
+
+            if !authentic {
+                self.torn = true;
+                return Ok(());
+            }
+
+            self.cursor = next;
+        }
+    }
+
+    /// Where the record after the one at `offset` starts, or `None` if the log
+    /// ends at `offset`.
+    fn next_record(&self, offset: u32) -> Result<Option<u32>, DecoderStorageReadError> {
+        if offset as usize + RECORD_HEADER_LEN > STORAGE_MAX {
+            return Ok(None);
+        }
+
+        let len = self.flash.read_32(self.bank_addr(self.bank) + offset)?;
+        if len == ERASED {
+            return Ok(None);
+        }
+
+        if len as usize > RECORD_MAX || offset as usize + record_size(len as usize) > STORAGE_MAX {
+            return Err(DecoderStorageReadError::FlashLengthTooLarge);
+        }
+
+        Ok(Some(offset + record_size(len as usize) as u32))
+    }
+
+    /// Reads the record at `offset` into the buffer, and returns whether it
+    /// decrypted. If it didn't, the buffer is left empty.
+    fn read_record(&mut self, offset: u32) -> Result<bool, DecoderStorageReadError> {
+        let address = self.bank_addr(self.bank) + offset;
+
+        let mut header = [0; RECORD_HEADER_LEN];
+        for (i, word) in header.chunks_mut(4).enumerate() {
+            word.copy_from_slice(&self.flash.read_32(address + 4 * i as u32)?.to_ne_bytes());
+        }
+
+        let len = u32::from_ne_bytes(header[0..4].try_into().expect("4==4")) as usize;
+        let nonce: XChacha20Nonce = header[4..28].try_into().expect("24==24");
+        let tag: XChacha20Tag = header[28..44].try_into().expect("16==16");
+
+        self.buf.clear();
+        self.read_bytes(address + RECORD_HEADER_LEN as u32, len)?;
+
+        let associated_data = self.associated_data(offset);
+        if decrypt_flash_buffer(&mut self.buf, &associated_data, &nonce, &tag).is_err() {
+            self.buf.zeroize();
+            self.buf.clear();
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Hands the data from each record in the log to `apply`, oldest first.
+    /// The log stops before the first record that doesn't check out. The old
+    /// firmware's blob is handed over whole, as the only record.
+    pub fn replay(&mut self, mut apply: impl FnMut(&[u8])) {
+        if self.version == LEGACY_VERSION {
+            let mut blob = heapless::Vec::<u8, LEGACY_MAX>::new();
+            if let Ok(true) = legacy::read_blob(&self.flash, self.base_addr, &mut blob) {
+                apply(&blob);
+            }
+            blob.zeroize();
+            return;
+        }
+
+        let mut offset = BANK_HEADER_LEN as u32;
+
+        while offset < self.cursor {
+            let Ok(Some(next)) = self.next_record(offset) else {
+                break;
+            };
+
+            if let Ok(true) = self.read_record(offset) {
+                apply(&self.buf);
+            }
+            self.buf.zeroize();
+            self.buf.clear();
+
+            offset = next;
+        }
+    }
+
+    /// Seals the buffer and appends it to the log.
+    ///
+    /// Returns false without writing anything if the log needs compacting
+    /// first, because the record doesn't fit in the bank, the log ends in a
+    /// torn record, or the log was saved by an older version. Either way the
+    /// buffer is zeroized and cleared.
+    pub fn append_buffer(&mut self) -> Result<bool, DecoderStorageWriteError> {
+        if let Err(err) = self.check_writable() {
+            self.buf.zeroize();
+            self.buf.clear();
+            return Err(err);
+        }
+
+        let size = record_size(self.buf.len());
+        if self.torn
+            || self.version != STORAGE_FORMAT_VERSION
+            || self.cursor as usize + size > STORAGE_MAX
+        {
+            self.buf.zeroize();
+            self.buf.clear();
+            return Ok(false);
+        }
+
+        let associated_data = self.associated_data(self.cursor);
+        let (nonce, tag) = encrypt_flash_buffer(&mut self.buf, &associated_data, &mut self.entropy)
+            .or(Err(DecoderStorageWriteError::CryptoError))?;
+
+        let mut header = [0xFF; RECORD_HEADER_LEN];
+        header[0..4].copy_from_slice(&(self.buf.len() as u32).to_ne_bytes());
+        header[4..28].copy_from_slice(&nonce);
+        header[28..44].copy_from_slice(&tag);
+
+        // The header goes first, so that a record the power is cut in the
+        // middle of has its length, and whatever it holds, the log ends
+        // there at boot.
+        let address = self.bank_addr(self.bank) + self.cursor;
+        write_bytes(&mut self.flash, address, &header)?;
+        write_bytes(
+            &mut self.flash,
+            address + RECORD_HEADER_LEN as u32,
+            &self.buf,
+        )?;
+        self.cursor += size as u32;
+
+        // zeroize and clear the buffer, no one is using it.
+        self.buf.zeroize();
+        self.buf.clear();
+
+        Ok(true)
+    }
+
+    /// Starts a new, empty log in the other bank. Records appended from here
+    /// on go to the new bank, but boot keeps using the old one until
+    /// [`finish_compaction`](Self::finish_compaction) is called.
+    pub fn begin_compaction(&mut self) -> Result<(), DecoderStorageWriteError> {
+        self.check_writable()?;
+        let bank = 1 - self.bank;
+        let generation = self.generation.wrapping_add(1);
+
+        // The first page goes first, so the bank header is gone before
+        // anything else is.
+        for page in 0..BANK_PAGES as u32 {
+            self.erase_page(self.bank_addr(bank) + page * FLASH_PAGE_SIZE)?;
+        }
+        self.flash.write_128(
+            self.bank_addr(bank),
+            &[
+                FLASH_INITIALIZED_MAGIC,
+                STORAGE_FORMAT_VERSION,
+                generation,
+                !generation,
+            ],
+        )?;
+
+        self.bank = bank;
+        self.version = STORAGE_FORMAT_VERSION;
+        self.generation = generation;
+        self.cursor = BANK_HEADER_LEN as u32;
+        self.torn = false;
+        Ok(())
+    }
+
+    /// Commits the bank that compaction started, so that boot uses it from now
+    /// on.
+    pub fn finish_compaction(&mut self) -> Result<(), DecoderStorageWriteError> {
+        self.check_writable()?;
+        self.flash
+            .write_32(self.bank_addr(self.bank) + COMMIT_OFFSET, COMMITTED)?;
+        Ok(())
+    }
+
+    /// What each record is bound to: the storage version, the bank generation
+    /// and where in the bank it is, so that records can't be moved around or
+    /// brought back from an old bank.
+    fn associated_data(&self, offset: u32) -> heapless::Vec<u8, 12> {
+        let mut associated_data = heapless::Vec::new();
+        associated_data.extend(self.version.to_le_bytes());
+        associated_data.extend(self.generation.to_le_bytes());
+        associated_data.extend(offset.to_le_bytes());
+        associated_data
+    }
+
+    /// Refuses to change the banks if newer firmware saved the log in them.
+    /// Only the subscription log is guarded, the timestamp and key logs are
+    /// written the same way whichever firmware saved the log.
+    fn check_writable(&self) -> Result<(), DecoderStorageWriteError> {
+        match self.outcome {
+            BootOutcome::NewerVersion(_) => Err(DecoderStorageWriteError::NewerVersion),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends `count` bytes of flash starting at `address` to the buffer.
+    fn read_bytes(&mut self, address: u32, count: usize) -> Result<(), DecoderStorageReadError> {
+        for offset in (0..count).step_by(4) {
+            let read = self.flash.read_32(address + offset as u32)?;
+            let bytes_left = (count - offset).min(4);
+
+            if self
+                .buf
+                .extend_from_slice(&read.to_ne_bytes()[..bytes_left])
+                .is_err()
+            {
+                return Err(DecoderStorageReadError::FlashLengthTooLarge);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Where bank `bank` starts.
+    fn bank_addr(&self, bank: u32) -> u32 {
+        self.base_addr + bank * BANK_PAGES as u32 * FLASH_PAGE_SIZE
+    }
+
+    fn erase_page(&mut self, address: u32) -> Result<(), FlashError> {
+        // Safety: these pages are reserved in memory.x, and thus cannot be
+        // pages that we are running code from.
+        unsafe { self.flash.erase_page(address) }
+    }
+
+    /// What was found in flash at boot.
+    pub fn boot_outcome(&self) -> BootOutcome {
+        self.outcome
+    }
+
+    /// The storage version that the log was saved with. This is only older
+    /// than [`STORAGE_FORMAT_VERSION`] until the log is first compacted.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// How many bytes of the [`STORAGE_MAX`] the log takes up.
+    pub fn stored_len(&self) -> u32 {
+        self.cursor
+    }
+
+    /// The highest frame timestamp bound that has been saved, or `None` if no
+    /// frame has ever been decoded.
+    pub fn time_bound(&self) -> Option<u64> {
+        self.time_log.bound()
+    }
+
+    /// Saves a new frame timestamp bound. This should always be higher than
+    /// the last one.
+    pub fn record_time_bound(&mut self, bound: u64) -> Result<(), DecoderStorageWriteError> {
+        Ok(self.time_log.record(&mut self.flash, bound)?)
+    }
+
+    /// The last satellite key rotation that was saved, if there has been one.
+    pub fn key_rotation(&self) -> Option<&KeyRotationBody> {
+        self.key_log.rotation()
+    }
+
+    /// Saves a satellite key rotation. This should always have a higher
+    /// counter than the last one.
+    pub fn record_key_rotation(
+        &mut self,
+        rotation: &KeyRotationBody,
+    ) -> Result<(), DecoderStorageWriteError> {
+        self.key_log
+            .record(&mut self.flash, &mut self.entropy, rotation)
+    }
+
+    pub fn get_buf_mut(&mut self) -> &mut heapless::Vec<u8, RECORD_MAX> {
+        &mut self.buf
+    }
+}
+
+/// Writes `bytes` to freshly erased flash at `address`, 128 bits at a time.
+/// The last block is padded out with 0xFF, which leaves those bytes erased.
+fn write_bytes<F: FlashBackend>(
+    flash: &mut F,
+    address: u32,
+    bytes: &[u8],
+) -> Result<(), FlashError> {
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let mut block = [0xFF; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+
+        let words = core::array::from_fn(|word| {
+            u32::from_ne_bytes(block[4 * word..4 * word + 4].try_into().expect("4==4"))
+        });
+        flash.write_128(address + 16 * i as u32, &words)?;
+    }
+
+    Ok(())
+}
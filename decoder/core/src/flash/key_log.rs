@@ -0,0 +1,156 @@
+//! A log of satellite key rotations, kept on its own pair of flash pages so
+//! that a rotated key is still the one in use after a reboot.
+//!
+//! Each entry is five 128-bit blocks: the nonce, the tag, and the sealed
+//! [`KeyRotationBody`], padded out with 0xFF. Entries are sealed with the
+//! flash key, so one that was only partly written doesn't decrypt and is
+//! skipped. Like the timestamp log, entries are appended in order until a page
+//! is full, and then the log erases the other page and carries on there. The
+//! page being erased is never the one holding the newest rotation.
+
+use decoder_protocol::KeyRotationBody;
+
+use super::{write_bytes, DecoderStorageWriteError, FlashBackend, FlashError, FLASH_PAGE_SIZE};
+use crate::crypto::{
+    decrypt_flash_buffer, encrypt_flash_buffer, EntropySource, XChacha20Nonce, XChacha20Tag,
+    XCHACHA20_NONCE_BYTES, XCHACHA20_TAG_BYTES,
+};
+
+/// How many pages the log takes up.
+pub const KEY_LOG_PAGES: u32 = 2;
+
+const NONCE_OFFSET: usize = 0;
+const TAG_OFFSET: usize = NONCE_OFFSET + XCHACHA20_NONCE_BYTES;
+const BODY_OFFSET: usize = TAG_OFFSET + XCHACHA20_TAG_BYTES;
+const ENTRY_SIZE: u32 = (BODY_OFFSET + KeyRotationBody::SIZE).next_multiple_of(16) as u32;
+const ENTRIES_PER_PAGE: u32 = FLASH_PAGE_SIZE / ENTRY_SIZE;
+const ERASED: [u32; 4] = [0xFFFFFFFF; 4];
+
+/// What entries are bound to, so that nothing else sealed with the flash key
+/// can pass for one. Subscription records are bound to 12 bytes (the version,
+/// generation and offset), and this is 22, so neither can be decrypted as the
+/// other. The first release's blob used a flash key of its own.
+const ASSOCIATED_DATA: &[u8] = b"satellite key rotation";
+
+pub(super) struct KeyLog {
+    base_addr: u32,
+    /// The rotation with the highest counter in the log, if there is one.
+    rotation: Option<KeyRotationBody>,
+    /// The page that the next entry goes on.
+    page: u32,
+    /// How many entries of `page` are already used.
+    used: u32,
+}
+
+impl KeyLog {
+    /// Reads the log on the pages starting at `base_addr`.
+    pub fn scan<F: FlashBackend>(flash: &F, base_addr: u32) -> Result<Self, FlashError> {
+        let mut log = Self {
+            base_addr,
+            rotation: None,
+            page: 0,
+            used: 0,
+        };
+        let mut used = [0; KEY_LOG_PAGES as usize];
+
+        for page in 0..KEY_LOG_PAGES {
+            for entry in 0..ENTRIES_PER_PAGE {
+                let address = log.entry_addr(page, entry);
+                // The first block goes first, so an entry is there once any of
+                // it is.
+                if flash.read_128(address)? == ERASED {
+                    break;
+                }
+                used[page as usize] = entry + 1;
+
+                if let Some(rotation) = read_entry(flash, address)? {
+                    if log
+                        .rotation
+                        .is_none_or(|newest| rotation.counter > newest.counter)
+                    {
+                        log.rotation = Some(rotation);
+                        log.page = page;
+                    }
+                }
+            }
+        }
+
+        log.used = used[log.page as usize];
+        Ok(log)
+    }
+
+    pub fn rotation(&self) -> Option<&KeyRotationBody> {
+        self.rotation.as_ref()
+    }
+
+    /// Appends a rotation, which should have a higher counter than the last
+    /// one.
+    pub fn record<F: FlashBackend>(
+        &mut self,
+        flash: &mut F,
+        entropy: &mut impl EntropySource,
+        rotation: &KeyRotationBody,
+    ) -> Result<(), DecoderStorageWriteError> {
+        let mut body = rotation.encode();
+        let (nonce, tag) = encrypt_flash_buffer(&mut body, ASSOCIATED_DATA, entropy)
+            .or(Err(DecoderStorageWriteError::CryptoError))?;
+
+        let mut entry = [0xFF; ENTRY_SIZE as usize];
+        entry[NONCE_OFFSET..TAG_OFFSET].copy_from_slice(&nonce);
+        entry[TAG_OFFSET..BODY_OFFSET].copy_from_slice(&tag);
+        entry[BODY_OFFSET..BODY_OFFSET + KeyRotationBody::SIZE].copy_from_slice(&body);
+
+        if self.used == ENTRIES_PER_PAGE {
+            let next = (self.page + 1) % KEY_LOG_PAGES;
+
+            // Safety: the log's pages are reserved in memory.x along with the
+            // rest of the storage, so we can't be running code from them.
+            unsafe {
+                flash.erase_page(self.page_addr(next))?;
+            }
+            self.page = next;
+            self.used = 0;
+        }
+
+        write_bytes(flash, self.entry_addr(self.page, self.used), &entry)?;
+        self.used += 1;
+        self.rotation = Some(*rotation);
+
+        Ok(())
+    }
+
+    fn page_addr(&self, page: u32) -> u32 {
+        self.base_addr + page * FLASH_PAGE_SIZE
+    }
+
+    fn entry_addr(&self, page: u32, entry: u32) -> u32 {
+        self.page_addr(page) + entry * ENTRY_SIZE
+    }
+}
+
+/// Reads the entry at `address`, if it decrypts.
+fn read_entry<F: FlashBackend>(
+    flash: &F,
+    address: u32,
+) -> Result<Option<KeyRotationBody>, FlashError> {
+    let mut entry = [0; ENTRY_SIZE as usize];
+    for (i, block) in entry.chunks_mut(16).enumerate() {
+        let words = flash.read_128(address + 16 * i as u32)?;
+        for (word, bytes) in words.iter().zip(block.chunks_mut(4)) {
+            bytes.copy_from_slice(&word.to_ne_bytes());
+        }
+    }
+
+    let nonce: XChacha20Nonce = entry[NONCE_OFFSET..TAG_OFFSET].try_into().expect("24==24");
+    let tag: XChacha20Tag = entry[TAG_OFFSET..BODY_OFFSET].try_into().expect("16==16");
+    let mut body: [u8; KeyRotationBody::SIZE] = entry
+        [BODY_OFFSET..BODY_OFFSET + KeyRotationBody::SIZE]
+        .try_into()
+        .expect("KeyRotationBody::SIZE");
+
+    if decrypt_flash_buffer(&mut body, ASSOCIATED_DATA, &nonce, &tag).is_err() {
+        return Ok(None);
+    }
+
+    Ok(Some(KeyRotationBody::decode(&body)))
+}
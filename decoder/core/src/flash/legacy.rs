@@ -0,0 +1,73 @@
+//! The single encrypted blob that firmware from before the storage log (storage
+//! version 1) kept its subscriptions in, at the start of the storage, where
+//! bank 0 is now.
+//!
+//! The blob starts with three 128-bit blocks of header: the magic, the length
+//! and the first 8 bytes of the nonce, then the rest of the nonce, then the MAC
+//! tag. The ciphertext follows. The magic was written last, so a blob that has
+//! it was written all the way through.
+
+use heapless::Vec;
+use zeroize::Zeroize;
+
+use super::{FlashBackend, FlashError};
+use crate::crypto::{decrypt_legacy_flash_buffer, XChacha20Nonce, XChacha20Tag};
+
+/// The storage version that the blob is read as.
+pub const LEGACY_VERSION: u32 = 1;
+
+/// The most that the blob could hold.
+pub const LEGACY_MAX: usize = 1024;
+
+pub(super) const MAGIC: u32 = 0x4d696b75;
+const HEADER_LEN: u32 = 16 * 3;
+
+/// Reads and decrypts the blob at `base_addr` into `buf`. Returns false, and
+/// leaves `buf` empty, if there isn't one or it doesn't decrypt.
+pub(super) fn read_blob<F: FlashBackend>(
+    flash: &F,
+    base_addr: u32,
+    buf: &mut Vec<u8, LEGACY_MAX>,
+) -> Result<bool, FlashError> {
+    buf.clear();
+
+    let [magic, len, nonce_1, nonce_2] = flash.read_128(base_addr)?;
+    if magic != MAGIC || len as usize > LEGACY_MAX {
+        return Ok(false);
+    }
+
+    let mut nonce: XChacha20Nonce = Default::default();
+    nonce[0..4].copy_from_slice(&nonce_1.to_ne_bytes());
+    nonce[4..8].copy_from_slice(&nonce_2.to_ne_bytes());
+    for (word, bytes) in flash
+        .read_128(base_addr + 16)?
+        .iter()
+        .zip(nonce[8..].chunks_mut(4))
+    {
+        bytes.copy_from_slice(&word.to_ne_bytes());
+    }
+
+    let mut tag: XChacha20Tag = Default::default();
+    for (word, bytes) in flash
+        .read_128(base_addr + 32)?
+        .iter()
+        .zip(tag.chunks_mut(4))
+    {
+        bytes.copy_from_slice(&word.to_ne_bytes());
+    }
+
+    for offset in (0..len).step_by(4) {
+        let word = flash.read_32(base_addr + HEADER_LEN + offset)?;
+        let bytes_left = (len - offset).min(4) as usize;
+        buf.extend_from_slice(&word.to_ne_bytes()[..bytes_left])
+            .expect("the length was checked against LEGACY_MAX");
+    }
+
+    if decrypt_legacy_flash_buffer(buf, &nonce, &tag).is_err() {
+        buf.zeroize();
+        buf.clear();
+        return Ok(false);
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,300 @@
+//! An in-memory model of the MAX78000's NOR flash, so that the storage code can
+//! be run somewhere other than the board.
+//!
+//! Nothing in the firmware uses this, it is here for host builds and tests.
+
+use super::{FlashBackend, FlashError, FLASH_PAGE_SIZE};
+use crate::crypto::EntropySource;
+
+const PAGE_WORDS: usize = (FLASH_PAGE_SIZE / 4) as usize;
+
+/// `PAGES` pages of simulated flash, starting at `base_addr`.
+///
+/// This behaves the same way that the flash controller does: erasing sets a
+/// page to all 1s, and a write that would need to set a bit back to 1 is
+/// refused with [`FlashError::NeedsErase`].
+pub struct SimFlash<const PAGES: usize> {
+    base_addr: u32,
+    pages: [[u32; PAGE_WORDS]; PAGES],
+}
+
+impl<const PAGES: usize> SimFlash<PAGES> {
+    /// The size of the whole simulated flash, in bytes.
+    pub const SIZE: usize = PAGES * FLASH_PAGE_SIZE as usize;
+
+    /// Creates freshly erased flash.
+    pub fn new(base_addr: u32) -> Self {
+        Self {
+            base_addr,
+            pages: [[0xFFFFFFFF; PAGE_WORDS]; PAGES],
+        }
+    }
+
+    /// Replaces the contents of the flash with an image saved by
+    /// [`SimFlash::save`]. The image must be exactly [`SimFlash::SIZE`] bytes.
+    pub fn load(&mut self, image: &[u8]) -> Result<(), FlashError> {
+        if image.len() != Self::SIZE {
+            return Err(FlashError::InvalidAddress);
+        }
+
+        let words = self.pages.iter_mut().flatten();
+        for (word, bytes) in words.zip(image.array_chunks::<4>()) {
+            *word = u32::from_le_bytes(*bytes);
+        }
+
+        Ok(())
+    }
+
+    /// Copies the contents of the flash out into `image`, which must be exactly
+    /// [`SimFlash::SIZE`] bytes.
+    pub fn save(&self, image: &mut [u8]) -> Result<(), FlashError> {
+        if image.len() != Self::SIZE {
+            return Err(FlashError::InvalidAddress);
+        }
+
+        let words = self.pages.iter().flatten();
+        for (word, bytes) in words.zip(image.chunks_exact_mut(4)) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    /// Turns an address into the page and word index that it points at,
+    /// checking that it is in range and aligned to `align` bytes.
+    fn locate(&self, address: u32, align: u32) -> Result<(usize, usize), FlashError> {
+        if address % align != 0 {
+            return Err(FlashError::InvalidAddress);
+        }
+
+        let offset = address
+            .checked_sub(self.base_addr)
+            .ok_or(FlashError::InvalidAddress)?;
+        let page = (offset / FLASH_PAGE_SIZE) as usize;
+        let word = ((offset % FLASH_PAGE_SIZE) / 4) as usize;
+
+        if page >= PAGES {
+            return Err(FlashError::InvalidAddress);
+        }
+
+        Ok((page, word))
+    }
+}
+
+impl<const PAGES: usize> FlashBackend for SimFlash<PAGES> {
+    fn read_32(&self, address: u32) -> Result<u32, FlashError> {
+        let (page, word) = self.locate(address, 4)?;
+        Ok(self.pages[page][word])
+    }
+
+    fn read_128(&self, address: u32) -> Result<[u32; 4], FlashError> {
+        let (page, word) = self.locate(address, 16)?;
+        Ok(self.pages[page][word..word + 4].try_into().expect("4 == 4"))
+    }
+
+    fn write_32(&mut self, address: u32, data: u32) -> Result<(), FlashError> {
+        // The flash controller only does 128-bit writes, so do what it does and
+        // rewrite the whole block around this word.
+        let (_, word) = self.locate(address, 4)?;
+        let block_addr = address & !0b1111;
+        let mut block = self.read_128(block_addr)?;
+        block[word % 4] = data;
+        self.write_128(block_addr, &block)
+    }
+
+    fn write_128(&mut self, address: u32, data: &[u32; 4]) -> Result<(), FlashError> {
+        let (page, word) = self.locate(address, 16)?;
+        let block = &mut self.pages[page][word..word + 4];
+
+        if block.iter().zip(data).any(|(old, new)| old & new != *new) {
+            return Err(FlashError::NeedsErase);
+        }
+
+        block.copy_from_slice(data);
+        Ok(())
+    }
+
+    unsafe fn erase_page(&mut self, address: u32) -> Result<(), FlashError> {
+        let (page, _) = self.locate(address, 4)?;
+        self.pages[page] = [0xFFFFFFFF; PAGE_WORDS];
+        Ok(())
+    }
+}
+
+/// Wraps another flash, and cuts the power after a set number of writes and
+/// erases. Once the power is out, nothing else reaches the flash, but every
+/// write still claims to succeed so that the code under test carries on as it
+/// would have up until the moment the board died.
+///
+/// A cut made with [`PowerCutFlash::cut_after`] lands between operations. One
+/// made with [`PowerCutFlash::tear_after`] lands partway through an operation,
+/// which only gets half done.
+pub struct PowerCutFlash<F> {
+    flash: F,
+    cut_after: Option<usize>,
+    torn: bool,
+    operations: usize,
+}
+
+/// How much of an operation reaches the flash.
+enum Operation {
+    Whole,
+    Torn,
+    Lost,
+}
+
+/// What a write leaves behind when the power goes partway through: only the
+/// low half of each word's bits got programmed.
+fn torn_word(old: u32, data: u32) -> u32 {
+    old & (data | 0xFFFF_0000)
+}
+
+impl<F: FlashBackend> PowerCutFlash<F> {
+    /// Never cuts the power, just counts the operations.
+    pub fn new(flash: F) -> Self {
+        Self {
+            flash,
+            cut_after: None,
+            torn: false,
+            operations: 0,
+        }
+    }
+
+    /// Cuts the power after `operations` writes and erases have gone through.
+    pub fn cut_after(flash: F, operations: usize) -> Self {
+        Self {
+            flash,
+            cut_after: Some(operations),
+            torn: false,
+            operations: 0,
+        }
+    }
+
+    /// Lets `operations` writes and erases through, then cuts the power
+    /// partway through the next one. A torn write only programs the low half
+    /// of each word's bits, and a torn erase only erases the first half of the
+    /// page. The torn operation is counted.
+    pub fn tear_after(flash: F, operations: usize) -> Self {
+        Self {
+            flash,
+            cut_after: Some(operations + 1),
+            torn: true,
+            operations: 0,
+        }
+    }
+
+    /// How many writes and erases have reached the flash.
+    pub fn operations(&self) -> usize {
+        self.operations
+    }
+
+    /// Whether the power has been cut.
+    pub fn is_cut(&self) -> bool {
+        self.cut_after
+            .is_some_and(|cut_after| self.operations >= cut_after)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Counts a write or erase, and says how much of it should happen.
+    fn operate(&mut self) -> Operation {
+        if self.is_cut() {
+            return Operation::Lost;
+        }
+
+        self.operations += 1;
+        if self.torn && self.is_cut() {
+            Operation::Torn
+        } else {
+            Operation::Whole
+        }
+    }
+}
+
+impl<F: FlashBackend> FlashBackend for PowerCutFlash<F> {
+    fn read_32(&self, address: u32) -> Result<u32, FlashError> {
+        self.flash.read_32(address)
+    }
+
+    fn read_128(&self, address: u32) -> Result<[u32; 4], FlashError> {
+        self.flash.read_128(address)
+    }
+
+    fn write_32(&mut self, address: u32, data: u32) -> Result<(), FlashError> {
+        match self.operate() {
+            Operation::Whole => self.flash.write_32(address, data),
+            Operation::Torn => {
+                let old = self.flash.read_32(address)?;
+                self.flash.write_32(address, torn_word(old, data))
+            }
+            Operation::Lost => Ok(()),
+        }
+    }
+
+    fn write_128(&mut self, address: u32, data: &[u32; 4]) -> Result<(), FlashError> {
+        match self.operate() {
+            Operation::Whole => self.flash.write_128(address, data),
+            Operation::Torn => {
+                let old = self.flash.read_128(address)?;
+                let torn = core::array::from_fn(|i| torn_word(old[i], data[i]));
+                self.flash.write_128(address, &torn)
+            }
+            Operation::Lost => Ok(()),
+        }
+    }
+
+    unsafe fn erase_page(&mut self, address: u32) -> Result<(), FlashError> {
+        match self.operate() {
+            // Safety: passed straight through, the caller has the same
+            // obligations.
+            Operation::Whole => unsafe { self.flash.erase_page(address) },
+            Operation::Torn => {
+                // Keep the second half of the page, erase it all, and put the
+                // second half back.
+                let page = address - address % FLASH_PAGE_SIZE;
+                let half = page + FLASH_PAGE_SIZE / 2;
+                let mut kept = [[0; 4]; PAGE_WORDS / 8];
+                for (i, block) in kept.iter_mut().enumerate() {
+                    *block = self.flash.read_128(half + 16 * i as u32)?;
+                }
+
+                // Safety: as above.
+                unsafe { self.flash.erase_page(address)? };
+
+                for (i, block) in kept.iter().enumerate() {
+                    if *block != [0xFFFFFFFF; 4] {
+                        self.flash.write_128(half + 16 * i as u32, block)?;
+                    }
+                }
+                Ok(())
+            }
+            Operation::Lost => Ok(()),
+        }
+    }
+}
+
+/// A deterministic stand-in for the TRNG. This is NOT random, it just needs to
+/// give us different nonces each time.
+pub struct SimEntropy {
+    state: u64,
+}
+
+impl SimEntropy {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on 0.
+        Self { state: seed | 1 }
+    }
+}
+
+impl EntropySource for SimEntropy {
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            chunk.copy_from_slice(&self.state.to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! A log of how far the frame timestamps have got, kept on its own pair of
+//! flash pages so that replay protection survives a reboot.
+//!
+//! Each entry is one 128-bit block of `[low, high, !low, !high]`, so an entry
+//! that was only partly written (or never written, and still all 1s) doesn't
+//! check out and is skipped. Entries are appended in order until a page is
+//! full, and then the log erases the other page and carries on there. The
+//! page being erased is never the one holding the newest entry, so a power cut
+//! at any point leaves the last good bound readable.
+
+use super::{FlashBackend, FlashError, FLASH_PAGE_SIZE};
+
+/// How many pages the log takes up.
+pub const TIME_LOG_PAGES: u32 = 2;
+
+const ENTRY_SIZE: u32 = 16;
+const ENTRIES_PER_PAGE: u32 = FLASH_PAGE_SIZE / ENTRY_SIZE;
+const ERASED: [u32; 4] = [0xFFFFFFFF; 4];
+
+fn encode(bound: u64) -> [u32; 4] {
+    let low = bound as u32;
+    let high = (bound >> 32) as u32;
+    [low, high, !low, !high]
+}
+
+fn decode(entry: [u32; 4]) -> Option<u64> {
+    let [low, high, not_low, not_high] = entry;
+    (low == !not_low && high == !not_high).then_some(((high as u64) << 32) | low as u64)
+}
+
+pub(super) struct TimeLog {
+    base_addr: u32,
+    /// The highest bound in the log, if anything has been logged at all.
+    bound: Option<u64>,
+    /// The page that the next entry goes on.
+    page: u32,
+    /// How many entries of `page` are already used.
+    used: u32,
+}
+
+impl TimeLog {
+    /// Reads the log on the pages starting at `base_addr`.
+    pub fn scan<F: FlashBackend>(flash: &F, base_addr: u32) -> Result<Self, FlashError> {
+        let mut log = Self {
+            base_addr,
+            bound: None,
+            page: 0,
+            used: 0,
+        };
+        let mut used = [0; TIME_LOG_PAGES as usize];
+
+        for page in 0..TIME_LOG_PAGES {
+            for entry in 0..ENTRIES_PER_PAGE {
+                let block = flash.read_128(log.entry_addr(page, entry))?;
+                if block == ERASED {
+                    break;
+                }
+                used[page as usize] = entry + 1;
+
+                if let Some(bound) = decode(block) {
+                    if log.bound.is_none_or(|newest| bound > newest) {
+                        log.bound = Some(bound);
+                        log.page = page;
+                    }
+                }
+            }
+        }
+
+        log.used = used[log.page as usize];
+        Ok(log)
+    }
+
+    pub fn bound(&self) -> Option<u64> {
+        self.bound
+    }
+
+    /// Appends a new bound, which should be higher than the last one.
+    pub fn record<F: FlashBackend>(&mut self, flash: &mut F, bound: u64) -> Result<(), FlashError> {
+        if self.used == ENTRIES_PER_PAGE {
+            let next = (self.page + 1) % TIME_LOG_PAGES;
+
+            // Safety: the log's pages are reserved in memory.x along with the
+            // rest of the storage, so we can't be running code from them.
+            unsafe {
+                flash.erase_page(self.page_addr(next))?;
+            }
+            self.page = next;
+            self.used = 0;
+        }
+
+        flash.write_128(self.entry_addr(self.page, self.used), &encode(bound))?;
+        self.used += 1;
+        self.bound = Some(bound);
+
+        Ok(())
+    }
+
+    fn page_addr(&self, page: u32) -> u32 {
+        self.base_addr + page * FLASH_PAGE_SIZE
+    }
+
+    fn entry_addr(&self, page: u32, entry: u32) -> u32 {
+        self.page_addr(page) + entry * ENTRY_SIZE
+    }
+}
@@ -0,0 +1,568 @@
+use core::fmt::Write as _;
+
+use decoder_protocol::{
+    CryptoHeader, DecodeRequest, DecodeRequestHeader, InfoResponse, KeyRotationBody,
+    KeyRotationUpdate, ListEntry, ListResponse, MessageHeader, Opcode, ProtocolError,
+    SubscriptionBody, SubscriptionUpdate, UnsubscribeBody, UnsubscribeUpdate, Update, BLOCK_LEN,
+    MAGIC, MAX_COMMAND_LEN, MAX_FRAME_LEN,
+};
+use embedded_io::{Read, Write};
+
+use crate::{
+    crypto::{verify_then_decrypt_for_decoder, CryptoError, EntropySource, VerifyingKeys},
+    decoder::{Decoder, Subscription, MAX_SUBSCRIPTION_COUNT},
+    flash::FlashBackend,
+};
+
+#[derive(Debug)]
+pub enum DecoderError {
+    /// Decoder expected an ACK in the protocol, but got something else.
+    ExpectedAckButGotOther,
+    /// Decoder has run out of subscription space.
+    NoMoreSubscriptionSpace,
+    /// Decoder was sent a frame that claims to be more than 64 bytes
+    FrameTooLarge,
+    /// Decoder does not have a valid subscription for the given channel.
+    NoSubscription,
+    /// Given timestamp does not fall within the subscription time window.
+    SubscriptionTimeMismatch,
+    /// Serialization failed while trying to write subscription update to flash.
+    SerializationFailed,
+    /// Saving the serialized data to flash failed
+    SavingFailed,
+    /// Reading what was saved to flash failed at boot, so the decoder can't
+    /// run.
+    LoadingFailed,
+    /// The subscriptions were saved by newer firmware, so we won't change them.
+    StorageFromNewerFirmware,
+    /// Failed to decrypt an encrypted payload.
+    FailedDecryption,
+    /// Received a frame from the past. We refuse to replay it.
+    FrameOutOfOrder,
+    /// Received a subscription or unsubscribe update that is no newer than
+    /// one already applied. We refuse to roll back to it.
+    StaleUpdate,
+    /// Received a packet which should have a consistent size that had a different size
+    PacketWrongSize,
+    /// Received a packet with an invalid command byte.
+    InvalidCommand,
+    /// Received a frame laid out in a version that we don't know.
+    UnknownFrameVersion,
+    /// Received an update laid out in a version that we don't know.
+    UnknownUpdateVersion,
+    /// Received a packet sealed with an AEAD suite that we don't support.
+    UnknownCryptoSuite,
+    /// Received a packet signed with a key that we don't have.
+    UnknownSigningKey,
+    /// Received a key rotation that is no newer than the last one applied.
+    StaleKeyRotation,
+    /// Received a key rotation to a key that can't be used to check
+    /// signatures.
+    BadVerifyingKey,
+    /// Reading from or writing to the host transport failed.
+    TransportFailed,
+}
+
+impl DecoderError {
+    /// Whether this is a packet that wasn't accepted as coming from the
+    /// deployment. These are all answered after the same delay, so that which
+    /// one it was doesn't come back any sooner than a bad tag would.
+    pub fn is_authentication_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::FailedDecryption | Self::UnknownSigningKey | Self::UnknownCryptoSuite
+        )
+    }
+
+    /// Get the message to be sent to console when this error is received
+    fn message(&self) -> &str {
+        match self {
+            Self::ExpectedAckButGotOther => "Expected ACK but got unexpected byte",
+            Self::NoMoreSubscriptionSpace => "Attempted to add a subscription, but subscription space is full",
+            Self::FrameTooLarge => "Was asked to decode a frame which is larger than 64 bytes",
+            Self::NoSubscription => "Was asked to decode a frame for (or unsubscribe from) a channel that we have no subscription for",
+            Self::SubscriptionTimeMismatch => "Was asked to decode a frame with timestamp thats invalid for our subscription.",
+            Self::SerializationFailed => "Failed to serialize subscription updates for flash",
+            Self::SavingFailed=> "Failed to save subscriptions to flash",
+            Self::LoadingFailed => "Failed to read the saved subscriptions or key rotation from flash, so the decoder can't start",
+            Self::StorageFromNewerFirmware => "Subscriptions were saved by newer firmware, so they can't be changed until that firmware is back",
+            Self::FailedDecryption => "Failed to decrypt a encrypted payload. This can mean that you used a subscription for a different decoder, or that your message was corrupted or tampered with.",
+            Self::FrameOutOfOrder => "Was asked to decode a frame with timestamp in the past",
+            Self::StaleUpdate => "Received a subscription or unsubscribe update that is no newer than one already applied",
+            Self::PacketWrongSize => "Received a packet which has a constant expected size with an invalid size for the packet type",
+            Self::InvalidCommand => "Received a command with a type byte that is not L, I, S, U, R, or D",
+            Self::UnknownFrameVersion => "Was asked to decode a frame in a format version that this firmware doesn't know",
+            Self::UnknownUpdateVersion => "Received an update in a format version that this firmware doesn't know",
+            Self::UnknownCryptoSuite => "Received a packet sealed with a crypto suite that this firmware doesn't support",
+            Self::UnknownSigningKey => "Received a packet signed with a key that this decoder doesn't have",
+            Self::StaleKeyRotation => "Received a key rotation that is no newer than the last one applied",
+            Self::BadVerifyingKey => "Received a key rotation to a key that isn't a usable Ed25519 public key",
+            Self::TransportFailed => "Failed to read from or write to the host transport",
+        }
+    }
+
+    /// Write this error to a given console
+    pub fn write_to_console<T: Read + Write>(&self, console: &mut DecoderConsole<T>) {
+        let message = self.message();
+        let _ = console.print_debug(message);
+        let _ = console.print_error(message);
+    }
+}
+
+impl From<CryptoError> for DecoderError {
+    fn from(err: CryptoError) -> Self {
+        match err {
+            CryptoError::UnknownSuite(_) => Self::UnknownCryptoSuite,
+            CryptoError::UnknownKey(_) => Self::UnknownSigningKey,
+            CryptoError::Failed => Self::FailedDecryption,
+        }
+    }
+}
+
+impl From<ProtocolError> for DecoderError {
+    fn from(err: ProtocolError) -> Self {
+        match err {
+            ProtocolError::WrongLength => Self::PacketWrongSize,
+            ProtocolError::BadMagic | ProtocolError::UnknownOpcode(_) => Self::InvalidCommand,
+            ProtocolError::UnknownFrameVersion(_) => Self::UnknownFrameVersion,
+            ProtocolError::UnknownUpdateVersion(_) => Self::UnknownUpdateVersion,
+        }
+    }
+}
+
+/// The console that the decoder talks to the host tools over.
+///
+/// This is generic over any [`embedded_io`] transport. On the board, this is
+/// the UART, but anything that can read and write bytes will do.
+pub struct DecoderConsole<T>(pub T);
+
+impl<T: Read + Write> DecoderConsole<T> {
+    /// Returns the packet parsed information from the packet header.
+    pub fn read_command_header(&mut self) -> Result<MessageHeader, DecoderError> {
+        let header = self.read_header()?;
+
+        // Error if this isn't something that the host should be sending us
+        // yet. We don't know what the length of something like this means, so
+        // just go back to looking for the next magic.
+        if header.opcode.command_length().is_none() {
+            return Err(DecoderError::InvalidCommand);
+        }
+
+        self.write_ack()?;
+
+        Ok(header)
+    }
+
+    /// Reads the body of a command, checking its length against what the
+    /// command should have first.
+    ///
+    /// If the length is wrong, the body is still taken off the wire before
+    /// returning the error. Otherwise we'd be left looking for the next magic
+    /// inside of it, and could end up running a `%` out of some ciphertext as a
+    /// command.
+    pub fn read_command_body(
+        &mut self,
+        header: MessageHeader,
+    ) -> Result<heapless::Vec<u8, MAX_COMMAND_LEN>, DecoderError> {
+        let length = header.length as usize;
+        let accepted = header
+            .opcode
+            .command_length()
+            .ok_or(DecoderError::InvalidCommand)?;
+
+        let mut reader: DecoderPayloadReader<'_, T> = DecoderPayloadReader::new(self, length);
+
+        if !accepted.contains(&length) {
+            reader.drain()?;
+
+            return Err(match header.opcode {
+                Opcode::Decode if length > *accepted.end() => DecoderError::FrameTooLarge,
+                _ => DecoderError::PacketWrongSize,
+            });
+        }
+
+        let mut body = heapless::Vec::new();
+        reader.extend_with_n_bytes(&mut body, length)?;
+        reader.finish_payload()?;
+
+        Ok(body)
+    }
+
+    // ACK
+
+    /// Reads an ACK off the wire. Returns Ok if an ACK is found, otherwise
+    /// Err containing the received byte
+    pub fn read_ack(&mut self) -> Result<(), DecoderError> {
+        // The whole header has to come off the wire, otherwise the length is
+        // left behind for the next read.
+        match self.read_header() {
+            Ok(header) if header == MessageHeader::ACK => Ok(()),
+            Err(DecoderError::TransportFailed) => Err(DecoderError::TransportFailed),
+            _ => Err(DecoderError::ExpectedAckButGotOther),
+        }
+    }
+
+    pub fn write_ack(&mut self) -> Result<(), DecoderError> {
+        self.write_header(MessageHeader::ACK)?;
+
+        // The host won't send anything else until it sees this ACK, so it
+        // can't be left sitting in a buffer.
+        self.flush()
+    }
+
+    // List
+
+    /// This function takes a Iterator of subscriptions, and sends out the list
+    /// response packet for them over UART
+    pub fn send_list<'a, I>(&mut self, subscriptions: I) -> Result<(), DecoderError>
+    where
+        I: Iterator<Item = &'a Subscription> + Clone,
+    {
+        let sub_count = subscriptions.clone().count();
+        let payload_len = ListResponse::length(sub_count) as u16;
+
+        self.write_header(MessageHeader::new(Opcode::List, payload_len))?;
+
+        self.read_ack()?;
+
+        let mut payload = DecoderPayloadWriter::new(self);
+
+        payload.write_bytes(&ListResponse::encode_count(sub_count))?;
+
+        for sub in subscriptions {
+            let entry = ListEntry {
+                channel_id: sub.channel_id,
+                start_time: sub.start_time,
+                end_time: sub.end_time,
+            };
+            payload.write_bytes(&entry.encode())?;
+        }
+
+        payload.finish_payload()?;
+
+        Ok(())
+    }
+
+    // Info
+    /// Sends the response to an Info command.
+    pub fn send_info(&mut self, info: &InfoResponse) -> Result<(), DecoderError> {
+        self.write_header(MessageHeader::new(Opcode::Info, InfoResponse::SIZE as u16))?;
+
+        self.read_ack()?;
+
+        let mut payload = DecoderPayloadWriter::new(self);
+        payload.write_bytes(&info.encode())?;
+        payload.finish_payload()?;
+
+        Ok(())
+    }
+
+    // Decode
+    /// Takes the body of a Decode Frame command, extracting the fields for the
+    /// crypto header, decrypts it, then writes the resulting frame back out.
+    pub fn decode_frame<F: FlashBackend, E: EntropySource>(
+        &mut self,
+        decoder: &mut Decoder<F, E>,
+        body: &[u8],
+    ) -> Result<(), DecoderError> {
+        let frame_length = DecodeRequest::frame_length(body.len())?;
+
+        let (header, payload) = body.split_at(DecodeRequestHeader::SIZE);
+        let header = header.try_into().expect("frame_length checked the length");
+        let header = DecodeRequestHeader::decode(header)?;
+
+        let mut payload: heapless::Vec<u8, MAX_FRAME_LEN> =
+            heapless::Vec::from_slice(payload).or(Err(DecoderError::FrameTooLarge))?;
+
+        let frame = decoder.decode_frame(&header, &mut payload)?;
+
+        // Write out the frame.
+        self.write_header(MessageHeader::new(Opcode::Decode, frame_length as u16))?;
+
+        self.read_ack()?;
+
+        let mut writer: DecoderPayloadWriter<'_, T> = DecoderPayloadWriter::new(self);
+        writer.write_bytes(frame)?;
+        writer.finish_payload()?;
+
+        Ok(())
+    }
+
+    // Debug
+    /// Sends a message to the host tools using the debug message type
+    pub fn print_debug(&mut self, message: &str) -> Result<(), DecoderError> {
+        let message = message.as_bytes();
+        self.write_header(MessageHeader::new(Opcode::Debug, message.len() as u16))?;
+
+        // Debug doesn't need ACK logic
+        self.write_bytes(message)
+    }
+
+    /// Tells the host which channels lost their subscriptions to make room
+    /// for a new one.
+    pub fn print_evicted(&mut self, channels: &[u32]) -> Result<(), DecoderError> {
+        // Enough for the message and every channel there could be, at up to
+        // 10 digits and a space each.
+        let mut message: heapless::String<{ 64 + 11 * MAX_SUBSCRIPTION_COUNT }> =
+            heapless::String::new();
+        let _ = write!(message, "Evicted expired subscriptions for channels:");
+        for channel in channels {
+            let _ = write!(message, " {channel}");
+        }
+
+        self.print_debug(&message)
+    }
+
+    // Error
+    /// Sends an error message to the host tools.
+    ///
+    /// THIS CLOSES THE HOST TOOL.
+    pub fn print_error(&mut self, message: &str) -> Result<(), DecoderError> {
+        let message = message.as_bytes();
+        self.write_header(MessageHeader::new(Opcode::Error, message.len() as u16))?;
+
+        self.read_ack()?;
+
+        let mut payload = DecoderPayloadWriter::new(self);
+        payload.write_bytes(message)?;
+        payload.finish_payload()?;
+
+        Ok(())
+    }
+
+    /// Send an empty payload with a particular type to the host tools.
+    pub fn send_empty_payload(&mut self, opcode: Opcode) -> Result<(), DecoderError> {
+        self.write_header(MessageHeader::new(opcode, 0))?;
+        self.read_ack()
+    }
+
+    // internal helpers
+    /// Reads the next message header off the wire, skipping anything before
+    /// the magic %.
+    fn read_header(&mut self) -> Result<MessageHeader, DecoderError> {
+        self.read_until_magic()?;
+
+        let mut header: [u8; MessageHeader::SIZE] = [MAGIC, 0, 0, 0];
+        self.read_bytes(&mut header[1..])?;
+
+        Ok(MessageHeader::decode(&header)?)
+    }
+
+    // reads
+    fn read_byte(&mut self) -> Result<u8, DecoderError> {
+        let mut byte: [u8; 1] = [0];
+        self.read_bytes(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), DecoderError> {
+        self.0
+            .read_exact(bytes)
+            .or(Err(DecoderError::TransportFailed))
+    }
+
+    /// Waits until the transport receives the magic % byte, consuming bytes as
+    /// it goes.
+    fn read_until_magic(&mut self) -> Result<(), DecoderError> {
+        // Whatever we've written has to make it out before we wait on the
+        // host, otherwise we can end up waiting on each other.
+        self.flush()?;
+        while self.read_byte()? != MAGIC {}
+        Ok(())
+    }
+
+    // writes
+    fn write_header(&mut self, header: MessageHeader) -> Result<(), DecoderError> {
+        self.write_bytes(&header.encode())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), DecoderError> {
+        self.0
+            .write_all(bytes)
+            .or(Err(DecoderError::TransportFailed))
+    }
+
+    fn flush(&mut self) -> Result<(), DecoderError> {
+        self.0.flush().or(Err(DecoderError::TransportFailed))
+    }
+}
+
+/// Checks the signature of an update that came in with `opcode`, then decrypts
+/// its `body` in place.
+fn open_update(
+    opcode: Opcode,
+    crypto: &CryptoHeader,
+    body: &mut [u8],
+    keys: &VerifyingKeys,
+) -> Result<(), DecoderError> {
+    let mut signed = [0; Update::SIGNED_MAX];
+    let signed = Update::signed_message(opcode, crypto, body, &mut signed)?;
+
+    verify_then_decrypt_for_decoder(keys, crypto, signed, &Update::associated_data(opcode), body)?;
+
+    Ok(())
+}
+
+/// Takes the body of a Subscribe command, and returns a subscription object,
+/// ready to be inserted into the subscription list by the Decoder. `keys` are
+/// what it has to be signed with.
+pub fn parse_subscription(body: &[u8], keys: &VerifyingKeys) -> Result<Subscription, DecoderError> {
+    let update: &[u8; SubscriptionUpdate::SIZE] =
+        body.try_into().or(Err(DecoderError::PacketWrongSize))?;
+
+    let SubscriptionUpdate {
+        crypto,
+        encrypted_body: mut body,
+    } = SubscriptionUpdate::decode(update)?;
+
+    open_update(Opcode::Subscribe, &crypto, &mut body, keys)?;
+
+    let body = SubscriptionBody::decode(&body);
+
+    Ok(Subscription {
+        channel_id: body.channel_id,
+        start_time: body.start_time,
+        end_time: body.end_time,
+        channel_key: body.channel_key,
+        issued: body.issued,
+    })
+}
+
+/// Takes the body of an Unsubscribe command, and returns the channel that it
+/// revokes and when it was issued. These are encrypted and signed exactly like
+/// subscriptions.
+pub fn parse_unsubscription(
+    body: &[u8],
+    keys: &VerifyingKeys,
+) -> Result<UnsubscribeBody, DecoderError> {
+    let update: &[u8; UnsubscribeUpdate::SIZE] =
+        body.try_into().or(Err(DecoderError::PacketWrongSize))?;
+
+    let UnsubscribeUpdate {
+        crypto,
+        encrypted_body: mut body,
+    } = UnsubscribeUpdate::decode(update)?;
+
+    open_update(Opcode::Unsubscribe, &crypto, &mut body, keys)?;
+
+    Ok(UnsubscribeBody::decode(&body))
+}
+
+/// Takes the body of a Rotate Key command, and returns the rotation. These are
+/// encrypted like subscriptions, and have to be signed with one of the `keys`
+/// that they replace.
+pub fn parse_key_rotation(
+    body: &[u8],
+    keys: &VerifyingKeys,
+) -> Result<KeyRotationBody, DecoderError> {
+    let update: &[u8; KeyRotationUpdate::SIZE] =
+        body.try_into().or(Err(DecoderError::PacketWrongSize))?;
+
+    let KeyRotationUpdate {
+        crypto,
+        encrypted_body: mut body,
+    } = KeyRotationUpdate::decode(update)?;
+
+    open_update(Opcode::RotateKey, &crypto, &mut body, keys)?;
+
+    Ok(KeyRotationBody::decode(&body))
+}
+
+/// This struct represents a payload being written to the wire.
+/// It handles expecting an ACK for every 256 bytes, as well as for the
+/// last block. The count at the front of a List body is part of the payload,
+/// so the blocks line up with the ones the host reads.
+struct DecoderPayloadWriter<'a, T> {
+    bytes_written: usize,
+    console: &'a mut DecoderConsole<T>,
+}
+
+impl<'a, T: Read + Write> DecoderPayloadWriter<'a, T> {
+    fn new(console: &'a mut DecoderConsole<T>) -> Self {
+        Self {
+            bytes_written: 0,
+            console,
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), DecoderError> {
+        // The host ACKs each whole block once it has read it, so that has to
+        // come back before the next block starts. The last block is ACKed by
+        // finish_payload.
+        if self.bytes_written % BLOCK_LEN == 0 && self.bytes_written != 0 {
+            self.console.read_ack()?;
+        }
+        self.console.write_bytes(&[byte])?;
+
+        self.bytes_written += 1;
+        Ok(())
+    }
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), DecoderError> {
+        for byte in bytes {
+            self.write_byte(*byte)?
+        }
+        Ok(())
+    }
+
+    fn finish_payload(self) -> Result<(), DecoderError> {
+        self.console.read_ack()
+    }
+}
+
+/// This struct represents a payload of `length` bytes being read from the
+/// wire. It handles writing an ACK for every 256 bytes, as well as for the
+/// last block.
+struct DecoderPayloadReader<'a, T> {
+    bytes_read: usize,
+    length: usize,
+    console: &'a mut DecoderConsole<T>,
+}
+
+impl<'a, T: Read + Write> DecoderPayloadReader<'a, T> {
+    fn new(console: &'a mut DecoderConsole<T>, length: usize) -> Self {
+        Self {
+            bytes_read: 0,
+            length,
+            console,
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8, DecoderError> {
+        let byte = self.console.read_byte()?;
+        self.bytes_read += 1;
+
+        // The host waits for an ACK as soon as it has sent a whole block. The
+        // last block is ACKed by finish_payload.
+        if self.bytes_read % BLOCK_LEN == 0 && self.bytes_read != self.length {
+            self.console.write_ack()?;
+        }
+        Ok(byte)
+    }
+
+    fn extend_with_n_bytes(
+        &mut self,
+        buf: &mut impl Extend<u8>,
+        count: usize,
+    ) -> Result<(), DecoderError> {
+        for _ in 0..count {
+            buf.extend([self.read_byte()?]);
+        }
+        Ok(())
+    }
+
+    /// Throws away the rest of the payload.
+    fn drain(mut self) -> Result<(), DecoderError> {
+        while self.bytes_read < self.length {
+            self.read_byte()?;
+        }
+        self.finish_payload()
+    }
+
+    fn finish_payload(self) -> Result<(), DecoderError> {
+        // An empty payload has no blocks, the header's ACK was the last one.
+        if self.length == 0 {
+            return Ok(());
+        }
+        self.console.write_ack()
+    }
+}
@@ -0,0 +1,29 @@
+//! What the decoder reports about itself in response to an Info command, for
+//! working out what's going on with a unit in the field.
+
+use decoder_protocol::{InfoResponse, BUILD_HASH_LEN};
+
+use crate::{
+    crypto::EntropySource,
+    decoder::{Decoder, MAX_SUBSCRIPTION_COUNT},
+    flash::{FlashBackend, STORAGE_MAX_U32},
+};
+
+include!(concat!(env!("OUT_DIR"), "/gen_info.rs"));
+
+/// Gathers up the decoder's identity and state.
+pub fn collect<F: FlashBackend, E: EntropySource>(decoder: &Decoder<F, E>) -> InfoResponse {
+    let used = decoder.get_subscriptions().iter().flatten().count();
+
+    InfoResponse {
+        decoder_id: DECODER_ID,
+        firmware_version: FIRMWARE_VERSION,
+        build_hash: BUILD_HASH,
+        max_subscriptions: MAX_SUBSCRIPTION_COUNT as u32,
+        free_subscriptions: (MAX_SUBSCRIPTION_COUNT - used) as u32,
+        last_timestamp: decoder.last_timestamp().unwrap_or(0),
+        storage_version: decoder.storage_version(),
+        storage_used: decoder.storage_used(),
+        storage_max: STORAGE_MAX_U32,
+    }
+}
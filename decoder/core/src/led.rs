@@ -0,0 +1,9 @@
+/// The colors that the decoder shows while it works. The firmware drives the
+/// RGB LED with these, anything else can do what it likes with them.
+pub trait StatusLed {
+    fn red(&mut self);
+    fn green(&mut self);
+    fn cyan(&mut self);
+    fn magenta(&mut self);
+    fn yellow(&mut self);
+}
@@ -0,0 +1,21 @@
+//! Everything the decoder does that doesn't need to touch the hardware: the
+//! host protocol, the crypto, subscription handling, and the storage format.
+//!
+//! The firmware crate hands this its peripherals through the [`flash::FlashBackend`],
+//! [`crypto::EntropySource`], [`led::StatusLed`] and [`timer::Clock`] traits,
+//! and an [`embedded_io`] transport for the console.
+#![no_std]
+// The only reason that this is unstable is because bikeshedding about the zero
+// case.
+#![feature(array_chunks)]
+// The crypto helpers deliberately don't say why they failed.
+#![allow(clippy::result_unit_err)]
+
+pub mod cmd_logic;
+pub mod crypto;
+pub mod decoder;
+pub mod flash;
+pub mod host_comms;
+pub mod info;
+pub mod led;
+pub mod timer;
@@ -0,0 +1,38 @@
+//! A millisecond clock, so that the decoder can hold back a response until
+//! some time after the command arrived.
+//!
+//! The firmware counts SysTick interrupts, the simulator uses the OS clock,
+//! and tests use `sim::MockClock` so that they don't have to actually wait.
+
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+
+/// How long a command that fails to decrypt, or names a suite or signing key
+/// that we don't have, takes to answer, counted from when its header arrived.
+/// This makes guessing at keys or tags over the UART painfully slow.
+pub const FAILED_DECRYPTION_DELAY_MS: u64 = 5000;
+
+/// A monotonic clock that counts in milliseconds.
+pub trait Clock {
+    /// Milliseconds since some fixed point, normally boot. Never goes
+    /// backwards.
+    fn now_ms(&self) -> u64;
+
+    /// Blocks until [`Clock::now_ms`] reaches `deadline_ms`. Returns straight
+    /// away if that's already passed.
+    fn wait_until(&mut self, deadline_ms: u64) {
+        while self.now_ms() < deadline_ms {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl<C: Clock> Clock for &mut C {
+    fn now_ms(&self) -> u64 {
+        (**self).now_ms()
+    }
+
+    fn wait_until(&mut self, deadline_ms: u64) {
+        (**self).wait_until(deadline_ms)
+    }
+}
@@ -0,0 +1,34 @@
+//! A clock that only moves when it's told to, for host builds and tests.
+//!
+//! Nothing in the firmware uses this.
+
+use super::Clock;
+
+/// A [`Clock`] that starts at 0 and never ticks on its own. Waiting on it
+/// jumps straight to the deadline, so a test can check how long the decoder
+/// would have waited without spending that time.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now_ms: u64,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward, as if `ms` milliseconds had passed.
+    pub fn advance(&mut self, ms: u64) {
+        self.now_ms += ms;
+    }
+}
+
+impl Clock for MockClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms
+    }
+
+    fn wait_until(&mut self, deadline_ms: u64) {
+        self.now_ms = self.now_ms.max(deadline_ms);
+    }
+}
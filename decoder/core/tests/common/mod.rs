@@ -0,0 +1,100 @@
+//! Helpers shared by the host-side tests.
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+
+use decoder_core::{
+    flash::{
+        sim::{SimEntropy, SimFlash},
+        DecoderStorage, STORAGE_PAGES,
+    },
+    led::StatusLed,
+};
+
+/// The base address that the tests put the simulated storage page at.
+pub const TEST_BASE_ADDR: u32 = 0x10044000;
+
+/// A transport that plays back a script of bytes from the host, and records
+/// everything the decoder writes. Running out of script reads as EOF.
+#[derive(Default)]
+pub struct ScriptedTransport {
+    input: VecDeque<u8>,
+    pub output: Vec<u8>,
+    /// How much had been written each time the decoder read from the script.
+    pub read_at: Vec<usize>,
+}
+
+impl ScriptedTransport {
+    pub fn new(script: &[u8]) -> Self {
+        Self {
+            input: script.iter().copied().collect(),
+            output: Vec::new(),
+            read_at: Vec::new(),
+        }
+    }
+
+    /// How much of the script the decoder hasn't read yet.
+    pub fn remaining(&self) -> usize {
+        self.input.len()
+    }
+}
+
+impl embedded_io::ErrorType for ScriptedTransport {
+    type Error = embedded_io::ErrorKind;
+}
+
+impl embedded_io::Read for ScriptedTransport {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_at.push(self.output.len());
+        let count = buf.len().min(self.input.len());
+        for (dst, src) in buf.iter_mut().zip(self.input.drain(..count)) {
+            *dst = src;
+        }
+        Ok(count)
+    }
+}
+
+impl embedded_io::Write for ScriptedTransport {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// An LED that doesn't do anything.
+pub struct NoLed;
+
+impl StatusLed for NoLed {
+    fn red(&mut self) {}
+    fn green(&mut self) {}
+    fn cyan(&mut self) {}
+    fn magenta(&mut self) {}
+    fn yellow(&mut self) {}
+}
+
+/// Packs a message the way the host tools do.
+pub fn message(opcode: u8, body: &[u8]) -> Vec<u8> {
+    let mut msg = vec![b'%', opcode];
+    msg.extend_from_slice(&(body.len() as u16).to_le_bytes());
+    msg.extend_from_slice(body);
+    msg
+}
+
+pub fn ack() -> Vec<u8> {
+    message(b'A', &[])
+}
+
+/// The secrets file that this build of decoder-core took its keys from.
+pub fn deployment_secrets() -> Vec<u8> {
+    std::fs::read(env!("DECODER_SECRETS_PATH")).expect("couldn't read the build's secrets")
+}
+
+/// Storage on freshly erased flash, as a decoder would find it on first boot.
+pub fn blank_storage() -> DecoderStorage<SimFlash<STORAGE_PAGES>, SimEntropy> {
+    let flash = SimFlash::new(TEST_BASE_ADDR);
+    DecoderStorage::init(flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap()
+}
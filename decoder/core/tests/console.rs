@@ -0,0 +1,340 @@
+//! Tests that drive `run_command` over a scripted transport, the same way the
+//! host tools would over UART.
+
+mod common;
+
+use common::{ack, blank_storage, message, NoLed, ScriptedTransport};
+use decoder_core::{
+    cmd_logic::run_command,
+    crypto::AeadSuite,
+    decoder::{Decoder, Subscription, MAX_SUBSCRIPTION_COUNT},
+    flash::{STORAGE_FORMAT_VERSION, STORAGE_MAX},
+    host_comms::{DecoderConsole, DecoderError},
+    timer::{sim::MockClock, Clock, FAILED_DECRYPTION_DELAY_MS},
+};
+use decoder_protocol::{InfoResponse, MessageHeader, Opcode, SubscriptionUpdate, UPDATE_VERSION};
+use decoder_secrets::parse_decoder_id;
+
+#[test]
+fn list_with_no_subscriptions() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let script = [message(b'L', &[]), ack(), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    assert!(run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new()
+    )
+    .is_ok());
+
+    let expected = [ack(), message(b'L', &0u32.to_le_bytes())].concat();
+    assert_eq!(console.0.output, expected);
+}
+
+#[test]
+fn list_reports_subscriptions() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+    let registered = decoder.register_subscription(Subscription {
+        channel_id: 3,
+        start_time: 5,
+        end_time: 500,
+        channel_key: [3; 32],
+        issued: 1,
+    });
+    assert!(registered.is_ok());
+
+    let script = [message(b'L', &[]), ack(), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    assert!(run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new()
+    )
+    .is_ok());
+
+    let mut body = 1u32.to_le_bytes().to_vec();
+    body.extend_from_slice(&3u32.to_le_bytes());
+    body.extend_from_slice(&5u64.to_le_bytes());
+    body.extend_from_slice(&500u64.to_le_bytes());
+    let expected = [ack(), message(b'L', &body)].concat();
+    assert_eq!(console.0.output, expected);
+}
+
+#[test]
+fn long_lists_wait_for_an_ack_every_block() {
+    // More than a block's worth, whatever the decoder's capacity is.
+    let subscriptions: Vec<Subscription> = (0..20)
+        .map(|channel_id| Subscription {
+            channel_id,
+            start_time: 5,
+            end_time: 500,
+            channel_key: [0; 32],
+            issued: 1,
+        })
+        .collect();
+
+    let script = [ack(), ack(), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+    assert!(console.send_list(subscriptions.iter()).is_ok());
+    assert_eq!(console.0.remaining(), 0);
+
+    let mut body = 20u32.to_le_bytes().to_vec();
+    for sub in &subscriptions {
+        body.extend_from_slice(&sub.channel_id.to_le_bytes());
+        body.extend_from_slice(&sub.start_time.to_le_bytes());
+        body.extend_from_slice(&sub.end_time.to_le_bytes());
+    }
+    assert_eq!(body.len(), 404);
+    assert_eq!(console.0.output, message(b'L', &body));
+
+    // tools/ectf25/utils/decoder.py ACKs the header, then each 256 byte block
+    // of the body as soon as it has read it, the count included.
+    let mut acked_at = console.0.read_at.clone();
+    acked_at.dedup();
+    assert_eq!(acked_at, [4, 4 + 256, 4 + 404]);
+}
+
+#[test]
+fn info_reports_the_decoder_state() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+    let registered = decoder.register_subscription(Subscription {
+        channel_id: 3,
+        start_time: 5,
+        end_time: 500,
+        channel_key: [3; 32],
+        issued: 1,
+    });
+    assert!(registered.is_ok());
+
+    let script = [message(b'I', &[]), ack(), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    assert!(run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new()
+    )
+    .is_ok());
+
+    let response = &console.0.output[MessageHeader::SIZE..];
+    let header = MessageHeader::new(Opcode::Info, InfoResponse::SIZE as u16);
+    assert_eq!(response[..MessageHeader::SIZE], header.encode());
+    let info = InfoResponse::decode(response[MessageHeader::SIZE..].try_into().unwrap());
+
+    assert_eq!(
+        info.decoder_id,
+        parse_decoder_id(env!("DECODER_ID")).unwrap()
+    );
+    assert_eq!(info.max_subscriptions, MAX_SUBSCRIPTION_COUNT as u32);
+    assert_eq!(info.free_subscriptions, MAX_SUBSCRIPTION_COUNT as u32 - 1);
+    assert_eq!(info.last_timestamp, 0);
+    assert_eq!(info.storage_version, STORAGE_FORMAT_VERSION);
+    assert!(info.storage_used > 0 && info.storage_used <= info.storage_max);
+    assert_eq!(info.storage_max, STORAGE_MAX as u32);
+}
+
+#[test]
+fn garbage_before_magic_is_skipped() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let script = [b"junk".to_vec(), message(b'L', &[]), ack(), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    assert!(run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new()
+    )
+    .is_ok());
+}
+
+#[test]
+fn unknown_command_is_rejected() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let mut console = DecoderConsole(ScriptedTransport::new(&message(b'Q', &[])));
+
+    let result = run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new(),
+    );
+    assert!(matches!(result, Err(DecoderError::InvalidCommand)));
+}
+
+#[test]
+fn list_with_payload_is_rejected() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let mut console = DecoderConsole(ScriptedTransport::new(&message(b'L', &[0; 4])));
+
+    let result = run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new(),
+    );
+    assert!(matches!(result, Err(DecoderError::PacketWrongSize)));
+
+    // The body still gets taken off the wire, and ACKed like any other.
+    assert_eq!(console.0.remaining(), 0);
+    assert_eq!(console.0.output, [ack(), ack()].concat());
+}
+
+/// Runs a bad command followed by a List, the way the firmware's main loop
+/// would, and checks that the List still gets answered.
+fn recovers_after(bad: &[u8], expected: fn(&DecoderError) -> bool) {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    // The ACKs after the bad command are for the error message.
+    let script = [bad, &ack(), &ack(), &message(b'L', &[]), &ack(), &ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    match run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new(),
+    ) {
+        Err(err) if expected(&err) => err.write_to_console(&mut console),
+        _ => panic!("the bad command should have failed"),
+    }
+
+    let list_start = console.0.output.len();
+    assert!(run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new()
+    )
+    .is_ok());
+    assert_eq!(console.0.remaining(), 0);
+
+    let expected = [ack(), message(b'L', &0u32.to_le_bytes())].concat();
+    assert_eq!(console.0.output[list_start..], expected);
+}
+
+#[test]
+fn oversized_decode_is_drained() {
+    // Nothing but magic, so any of it left on the wire would be found.
+    recovers_after(&message(b'D', &[b'%'; 600]), |err| {
+        matches!(err, DecoderError::FrameTooLarge)
+    });
+}
+
+#[test]
+fn undersized_decode_is_drained() {
+    let body = b"%L\0\0".repeat(4);
+    recovers_after(&message(b'D', &body), |err| {
+        matches!(err, DecoderError::PacketWrongSize)
+    });
+}
+
+#[test]
+fn wrong_size_subscription_is_drained() {
+    recovers_after(&message(b'S', &[b'%'; 100]), |err| {
+        matches!(err, DecoderError::PacketWrongSize)
+    });
+}
+
+/// A subscription update that gets as far as checking its signature, but is
+/// otherwise `fill`.
+fn undecryptable_subscription(fill: u8) -> Vec<u8> {
+    let mut update = vec![fill; SubscriptionUpdate::SIZE];
+    update[0] = UPDATE_VERSION;
+    update[1] = AeadSuite::CURRENT.id();
+    update[2] = 0;
+    update
+}
+
+#[test]
+fn undecryptable_subscription_is_consumed() {
+    recovers_after(&message(b'S', &undecryptable_subscription(b'%')), |err| {
+        matches!(err, DecoderError::FailedDecryption)
+    });
+}
+
+#[test]
+fn failed_decryption_is_delayed() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let mut console = DecoderConsole(ScriptedTransport::new(&message(
+        b'S',
+        &undecryptable_subscription(0),
+    )));
+    let mut clock = MockClock::new();
+    clock.advance(1234);
+
+    let result = run_command(&mut console, &mut decoder, &mut NoLed, &mut clock);
+    assert!(matches!(result, Err(DecoderError::FailedDecryption)));
+
+    // The delay counts from when the command came in, not from boot.
+    assert_eq!(clock.now_ms(), 1234 + FAILED_DECRYPTION_DELAY_MS);
+}
+
+#[test]
+fn other_commands_are_not_delayed() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let script = [message(b'L', &[]), ack(), ack(), message(b'S', &[0; 100])].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+    let mut clock = MockClock::new();
+
+    assert!(run_command(&mut console, &mut decoder, &mut NoLed, &mut clock).is_ok());
+    let result = run_command(&mut console, &mut decoder, &mut NoLed, &mut clock);
+    assert!(matches!(result, Err(DecoderError::PacketWrongSize)));
+
+    assert_eq!(clock.now_ms(), 0);
+}
+
+#[test]
+fn long_bodies_are_acked_every_block() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let mut console = DecoderConsole(ScriptedTransport::new(&message(b'D', &[0; 600])));
+
+    let result = run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new(),
+    );
+    assert!(matches!(result, Err(DecoderError::FrameTooLarge)));
+
+    // One for the header, then 256, 512 and the last 88 bytes.
+    assert_eq!(console.0.output, ack().repeat(4));
+}
+
+#[test]
+fn running_out_of_input_is_a_transport_error() {
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let mut console = DecoderConsole(ScriptedTransport::new(b"%L"));
+
+    let result = run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new(),
+    );
+    assert!(matches!(result, Err(DecoderError::TransportFailed)));
+}
@@ -0,0 +1,715 @@
+//! Round trips frames and subscriptions from the Rust host tools through the
+//! decoder, so that the two can't drift apart on the packet format or keys.
+
+mod common;
+
+use common::{
+    ack, blank_storage, deployment_secrets, message, NoLed, ScriptedTransport, TEST_BASE_ADDR,
+};
+use decoder_core::{
+    cmd_logic::run_command,
+    crypto::{AeadSuite, VerifyingKeys, ENCODER_CRYPTO_HEADER_LEN},
+    decoder::{
+        Decoder, Subscription, MAX_SUBSCRIPTION_COUNT, TIMESTAMPS_PER_SECOND, TIME_BOUND_STRIDE,
+    },
+    flash::{
+        sim::{SimEntropy, SimFlash},
+        BootOutcome, DecoderStorage, FlashBackend, STORAGE_FORMAT_VERSION, STORAGE_PAGES,
+    },
+    host_comms::{parse_subscription, DecoderConsole, DecoderError},
+    timer::{sim::MockClock, Clock, FAILED_DECRYPTION_DELAY_MS},
+};
+use decoder_host::{
+    encoder::Encoder,
+    subscription::{gen_key_rotation, gen_subscription, gen_unsubscribe},
+};
+use decoder_protocol::{
+    CryptoHeader, DecodeRequestHeader, SubscriptionUpdate, FRAME_VERSION, MAX_FRAME_LEN,
+    UPDATE_VERSION,
+};
+use decoder_secrets::{parse_decoder_id, Secrets};
+use ed25519_dalek::SigningKey;
+
+/// The secret half of the next signing key in `core/test.secrets`, which is
+/// accepted under key ID 1.
+const NEXT_SIGNING_SK: [u8; 32] = [
+    0x2d, 0x5a, 0x71, 0xdb, 0x7b, 0x57, 0x96, 0xe4, 0xf0, 0x95, 0xe3, 0x84, 0xbb, 0x8e, 0x34, 0xc3,
+    0xaa, 0x06, 0x36, 0x6e, 0x81, 0xa3, 0xcc, 0x95, 0x68, 0x0a, 0x86, 0xcb, 0xfe, 0xb2, 0xc2, 0x57,
+];
+
+/// Splits an encoded packet into the arguments for `Decoder::decode_frame`.
+fn split_packet(packet: &[u8]) -> (DecodeRequestHeader, heapless::Vec<u8, MAX_FRAME_LEN>) {
+    let (header, payload) = packet.split_at(DecodeRequestHeader::SIZE);
+
+    (
+        DecodeRequestHeader::decode(header.try_into().unwrap()).unwrap(),
+        heapless::Vec::from_slice(payload).unwrap(),
+    )
+}
+
+#[test]
+fn packet_layout_matches_the_decoder() {
+    let encoder = Encoder::new(&deployment_secrets()).unwrap();
+    let packet = encoder.encode(0, &[7; 64], 1).unwrap();
+
+    assert_eq!(packet.len(), 1 + 4 + 8 + ENCODER_CRYPTO_HEADER_LEN + 64);
+    assert_eq!(packet[0], FRAME_VERSION);
+}
+
+#[test]
+fn channel_0_round_trips() {
+    let encoder = Encoder::new(&deployment_secrets()).unwrap();
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    for (timestamp, frame) in [(10, &b"first"[..]), (11, b""), (500, &[0xAB; 64])] {
+        let packet = encoder.encode(0, frame, timestamp).unwrap();
+        let (header, mut payload) = split_packet(&packet);
+
+        let decoded = decoder.decode_frame(&header, &mut payload);
+        assert_eq!(decoded.ok(), Some(frame));
+    }
+}
+
+#[test]
+fn subscribed_channel_round_trips() {
+    let secrets = deployment_secrets();
+    let encoder = Encoder::new(&secrets).unwrap();
+    let channel_key = *Secrets::from_json(&secrets)
+        .unwrap()
+        .channel_key(1)
+        .unwrap();
+
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let packet = encoder.encode(1, b"not yet", 5).unwrap();
+    let (header, mut payload) = split_packet(&packet);
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(decoded, Err(DecoderError::NoSubscription)));
+
+    let registered = decoder.register_subscription(Subscription {
+        channel_id: 1,
+        start_time: 0,
+        end_time: 100,
+        channel_key,
+        issued: 1,
+    });
+    assert!(registered.is_ok());
+
+    let packet = encoder.encode(1, b"subscribed", 50).unwrap();
+    let (header, mut payload) = split_packet(&packet);
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert_eq!(decoded.ok(), Some(&b"subscribed"[..]));
+
+    let packet = encoder.encode(1, b"expired", 101).unwrap();
+    let (header, mut payload) = split_packet(&packet);
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(
+        decoded,
+        Err(DecoderError::SubscriptionTimeMismatch)
+    ));
+}
+
+#[test]
+fn stale_and_tampered_frames_are_rejected() {
+    let encoder = Encoder::new(&deployment_secrets()).unwrap();
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let packet = encoder.encode(0, b"frame", 20).unwrap();
+    let (header, mut payload) = split_packet(&packet);
+    assert!(decoder.decode_frame(&header, &mut payload).is_ok());
+
+    // Same timestamp again.
+    let packet = encoder.encode(0, b"frame", 20).unwrap();
+    let (header, mut payload) = split_packet(&packet);
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(decoded, Err(DecoderError::FrameOutOfOrder)));
+
+    let mut packet = encoder.encode(0, b"frame", 21).unwrap();
+    *packet.last_mut().unwrap() ^= 1;
+    let (header, mut payload) = split_packet(&packet);
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(decoded, Err(DecoderError::FailedDecryption)));
+}
+
+#[test]
+fn signature_is_checked_before_decrypting() {
+    let secrets = deployment_secrets();
+    let encoder = Encoder::new(&secrets).unwrap();
+    let channel_0_key = *Secrets::from_json(&secrets)
+        .unwrap()
+        .channel_key(0)
+        .unwrap();
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    // A bad signature leaves the ciphertext untouched.
+    let packet = encoder.encode(0, b"frame", 30).unwrap();
+    let (mut header, mut payload) = split_packet(&packet);
+    header.crypto.signature[0] ^= 1;
+    let ciphertext = payload.clone();
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(decoded, Err(DecoderError::FailedDecryption)));
+    assert_eq!(payload, ciphertext);
+
+    // The signature covers the channel ID, so a frame can't be moved to
+    // another channel even if the key would decrypt it.
+    let registered = decoder.register_subscription(Subscription {
+        channel_id: 1,
+        start_time: 0,
+        end_time: 100,
+        channel_key: channel_0_key,
+        issued: 1,
+    });
+    assert!(registered.is_ok());
+    let (mut header, mut payload) = split_packet(&packet);
+    header.channel_id = 1;
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(decoded, Err(DecoderError::FailedDecryption)));
+    assert_eq!(payload, ciphertext);
+
+    // And the nonce.
+    let (mut header, mut payload) = split_packet(&packet);
+    header.crypto.nonce[0] ^= 1;
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(decoded, Err(DecoderError::FailedDecryption)));
+    assert_eq!(payload, ciphertext);
+}
+
+#[test]
+fn clear_header_is_checked_before_any_crypto() {
+    let encoder = Encoder::new(&deployment_secrets()).unwrap();
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let registered = decoder.register_subscription(Subscription {
+        channel_id: 1,
+        start_time: 0,
+        end_time: 100,
+        channel_key: [0; 32],
+        issued: 1,
+    });
+    assert!(registered.is_ok());
+
+    let packet = encoder.encode(0, b"frame", 20).unwrap();
+    let (header, mut payload) = split_packet(&packet);
+    assert!(decoder.decode_frame(&header, &mut payload).is_ok());
+
+    // Frames that would be refused anyway are turned away on their header
+    // alone, so they don't need anything valid after it, and aren't held back
+    // like a failed decryption.
+    let mut header_only = |channel_id, timestamp| {
+        let header = DecodeRequestHeader {
+            channel_id,
+            timestamp,
+            crypto: CryptoHeader {
+                suite: AeadSuite::CURRENT.id(),
+                key_id: 0,
+                nonce: [0; 24],
+                tag: [0; 16],
+                signature: [0; 64],
+            },
+        };
+        let script = [message(b'D', &header.encode()), ack()].concat();
+        let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+        let mut clock = MockClock::new();
+        let result = run_command(&mut console, &mut decoder, &mut NoLed, &mut clock);
+        assert_eq!(clock.now_ms(), 0);
+        result
+    };
+    assert!(matches!(
+        header_only(0, 20),
+        Err(DecoderError::FrameOutOfOrder)
+    ));
+    assert!(matches!(
+        header_only(1, 200),
+        Err(DecoderError::SubscriptionTimeMismatch)
+    ));
+    assert!(matches!(
+        header_only(5, 30),
+        Err(DecoderError::NoSubscription)
+    ));
+
+    // The timestamp is still covered by the signature and the tag.
+    let mut packet = encoder.encode(0, b"frame", 40).unwrap();
+    packet[5] ^= 1;
+    let (header, mut payload) = split_packet(&packet);
+    assert_eq!(header.timestamp, 41);
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(decoded, Err(DecoderError::FailedDecryption)));
+}
+
+#[test]
+fn any_accepted_signing_key_works() {
+    let mut secrets = Secrets::from_json(&deployment_secrets()).unwrap();
+    let next_key = SigningKey::from_bytes(&NEXT_SIGNING_SK);
+    // Only the test deployment has the next key in it.
+    if secrets.verifying_keys.get(&1) != Some(&next_key.verifying_key().to_bytes()) {
+        return;
+    }
+
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    // The deployment moves over to the next key, without the decoder changing.
+    secrets.signing_sk = NEXT_SIGNING_SK;
+    secrets.signing_key_id = 1;
+    let encoder = Encoder::new(&secrets.to_json()).unwrap();
+
+    let packet = encoder.encode(0, b"next key", 1).unwrap();
+    let (header, mut payload) = split_packet(&packet);
+    assert_eq!(header.crypto.key_id, 1);
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert_eq!(decoded.ok(), Some(&b"next key"[..]));
+
+    // The key ID picks the key, so the signature doesn't check out under
+    // another one.
+    let packet = encoder.encode(0, b"next key", 2).unwrap();
+    let (mut header, mut payload) = split_packet(&packet);
+    header.crypto.key_id = 0;
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(decoded, Err(DecoderError::FailedDecryption)));
+}
+
+#[test]
+fn unknown_suites_and_keys_are_delayed_like_failed_decryption() {
+    let mut secrets = Secrets::from_json(&deployment_secrets()).unwrap();
+    let decoder_id = parse_decoder_id(env!("DECODER_ID")).unwrap();
+    let encoder = Encoder::new(&secrets.to_json()).unwrap();
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let mut run = |body: &[u8], opcode| {
+        let script = [message(opcode, body), ack()].concat();
+        let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+        // Neither is checked with any crypto, but the answer is held back all
+        // the same.
+        let mut clock = MockClock::new();
+        let result = run_command(&mut console, &mut decoder, &mut NoLed, &mut clock);
+        assert_eq!(clock.now_ms(), FAILED_DECRYPTION_DELAY_MS);
+        result
+    };
+
+    let mut packet = encoder.encode(0, b"frame", 1).unwrap();
+    packet[13] = 0xEE;
+    assert!(matches!(
+        run(&packet, b'D'),
+        Err(DecoderError::UnknownCryptoSuite)
+    ));
+
+    let mut packet = encoder.encode(0, b"frame", 1).unwrap();
+    packet[14] = 0xEE;
+    assert!(matches!(
+        run(&packet, b'D'),
+        Err(DecoderError::UnknownSigningKey)
+    ));
+
+    // Subscriptions are the same.
+    secrets.signing_key_id = 0xEE;
+    let subscription = gen_subscription(&secrets, decoder_id, 5, 50, 2).unwrap();
+    assert!(matches!(
+        run(&subscription, b'S'),
+        Err(DecoderError::UnknownSigningKey)
+    ));
+}
+
+#[test]
+fn unknown_frame_versions_are_refused() {
+    let encoder = Encoder::new(&deployment_secrets()).unwrap();
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let mut packet = encoder.encode(0, b"frame", 1).unwrap();
+    packet[0] = 1;
+    let script = [message(b'D', &packet), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    let mut clock = MockClock::new();
+    let result = run_command(&mut console, &mut decoder, &mut NoLed, &mut clock);
+    assert!(matches!(result, Err(DecoderError::UnknownFrameVersion)));
+    // Nothing was decrypted, so there's no need to hold the answer back.
+    assert_eq!(clock.now_ms(), 0);
+}
+
+#[test]
+fn frames_still_decode_under_storage_from_newer_firmware() {
+    let encoder = Encoder::new(&deployment_secrets()).unwrap();
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let newer = STORAGE_FORMAT_VERSION + 1;
+    flash
+        .write_128(TEST_BASE_ADDR, &[0x4d696b76, newer, 1, !1])
+        .unwrap();
+
+    let decode = |flash: &mut SimFlash<STORAGE_PAGES>, timestamp| {
+        let mut storage = DecoderStorage::init(flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        assert_eq!(storage.boot_outcome(), BootOutcome::NewerVersion(newer));
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+
+        let packet = encoder.encode(0, b"emergency", timestamp).unwrap();
+        let (header, mut payload) = split_packet(&packet);
+        decoder
+            .decode_frame(&header, &mut payload)
+            .map(|frame| frame.to_vec())
+    };
+
+    assert_eq!(decode(&mut flash, 1000).ok(), Some(b"emergency".to_vec()));
+    // The bound was still saved, so the frame can't be replayed after a
+    // reboot.
+    assert!(matches!(
+        decode(&mut flash, 1000),
+        Err(DecoderError::FrameOutOfOrder)
+    ));
+}
+
+#[test]
+fn replay_protection_survives_a_reboot() {
+    let encoder = Encoder::new(&deployment_secrets()).unwrap();
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let decode = |flash: &mut SimFlash<STORAGE_PAGES>, timestamp| {
+        let mut storage = DecoderStorage::init(flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+
+        let packet = encoder.encode(0, b"frame", timestamp).unwrap();
+        let (header, mut payload) = split_packet(&packet);
+        decoder.decode_frame(&header, &mut payload).map(|_| ())
+    };
+
+    assert!(decode(&mut flash, 1000).is_ok());
+
+    // Everything up to the saved bound is refused after a reboot, including
+    // the frame that we just decoded.
+    for timestamp in [1000, 999, 1000 + TIME_BOUND_STRIDE] {
+        assert!(matches!(
+            decode(&mut flash, timestamp),
+            Err(DecoderError::FrameOutOfOrder)
+        ));
+    }
+    assert!(decode(&mut flash, 1001 + TIME_BOUND_STRIDE).is_ok());
+    assert!(matches!(
+        decode(&mut flash, 1001 + TIME_BOUND_STRIDE),
+        Err(DecoderError::FrameOutOfOrder)
+    ));
+}
+
+#[test]
+fn the_frame_after_the_last_one_before_a_reboot_is_refused() {
+    let encoder = Encoder::new(&deployment_secrets()).unwrap();
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let frame = |timestamp| {
+        let packet = encoder.encode(0, b"frame", timestamp).unwrap();
+        split_packet(&packet)
+    };
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        for timestamp in [1000, 2000] {
+            let (header, mut payload) = frame(timestamp);
+            assert!(decoder.decode_frame(&header, &mut payload).is_ok());
+        }
+    }
+
+    // Without the reboot, 2001 would have been fine. After it, the decoder
+    // only knows the bound that the first frame saved, and 2001 is under it.
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(storage.time_bound(), Some(1000 + TIME_BOUND_STRIDE));
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let (header, mut payload) = frame(2001);
+    assert!(matches!(
+        decoder.decode_frame(&header, &mut payload),
+        Err(DecoderError::FrameOutOfOrder)
+    ));
+
+    // The stride is ten seconds in the build's timestamp unit.
+    assert_eq!(TIME_BOUND_STRIDE, 10 * TIMESTAMPS_PER_SECOND);
+    let (header, mut payload) = frame(1001 + TIME_BOUND_STRIDE);
+    assert!(decoder.decode_frame(&header, &mut payload).is_ok());
+}
+
+#[test]
+fn decode_command_round_trips() {
+    let encoder = Encoder::new(&deployment_secrets()).unwrap();
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let packet = encoder.encode(0, b"over the wire", 1).unwrap();
+    let script = [message(b'D', &packet), ack(), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    assert!(run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new()
+    )
+    .is_ok());
+
+    let expected = [ack(), ack(), message(b'D', b"over the wire")].concat();
+    assert_eq!(console.0.output, expected);
+}
+
+#[test]
+fn generated_subscription_is_accepted() {
+    let secrets = Secrets::from_json(&deployment_secrets()).unwrap();
+    let decoder_id = parse_decoder_id(env!("DECODER_ID")).unwrap();
+    let mut storage = blank_storage();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let subscription = gen_subscription(&secrets, decoder_id, 5, 50, 2).unwrap();
+    let script = [message(b'S', &subscription), ack(), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    assert!(run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new()
+    )
+    .is_ok());
+
+    let sub = decoder.get_subscription(2).unwrap();
+    assert_eq!((sub.start_time, sub.end_time), (5, 50));
+    assert_eq!(&sub.channel_key, secrets.channel_key(2).unwrap());
+
+    // A subscription for another decoder doesn't decrypt.
+    let subscription = gen_subscription(&secrets, decoder_id ^ 1, 5, 50, 3).unwrap();
+    let script = [message(b'S', &subscription), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    let mut clock = MockClock::new();
+
+    let result = run_command(&mut console, &mut decoder, &mut NoLed, &mut clock);
+    assert!(matches!(result, Err(DecoderError::FailedDecryption)));
+    assert_eq!(clock.now_ms(), FAILED_DECRYPTION_DELAY_MS);
+}
+
+#[test]
+fn generated_updates_are_checked_before_decrypting() {
+    let secrets = Secrets::from_json(&deployment_secrets()).unwrap();
+    let decoder_id = parse_decoder_id(env!("DECODER_ID")).unwrap();
+    let keys = VerifyingKeys::default();
+    let subscription = gen_subscription(&secrets, decoder_id, 5, 50, 2).unwrap();
+    assert_eq!(subscription[0], UPDATE_VERSION);
+    assert!(parse_subscription(&subscription, &keys).is_ok());
+
+    // The unversioned layout from before signed the plaintext.
+    let mut update = subscription.clone();
+    update[0] = 1;
+    assert!(matches!(
+        parse_subscription(&update, &keys),
+        Err(DecoderError::UnknownUpdateVersion)
+    ));
+
+    // The ids, the nonce, the tag and the ciphertext are all covered.
+    for byte in [1, 2, 3, 30, 50, SubscriptionUpdate::SIZE - 1] {
+        let mut update = subscription.clone();
+        update[byte] ^= 1;
+        assert!(parse_subscription(&update, &keys).is_err());
+    }
+    // And a bad signature is refused the same as a bad tag.
+    let mut update = subscription.clone();
+    update[1 + 2 + 24 + 16] ^= 1;
+    assert!(matches!(
+        parse_subscription(&update, &keys),
+        Err(DecoderError::FailedDecryption)
+    ));
+}
+
+#[test]
+fn full_decoder_reports_evicted_subscriptions() {
+    let secrets = Secrets::from_json(&deployment_secrets()).unwrap();
+    let decoder_id = parse_decoder_id(env!("DECODER_ID")).unwrap();
+    let mut storage = blank_storage();
+    storage.record_time_bound(100).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    for channel_id in 10..10 + MAX_SUBSCRIPTION_COUNT as u32 {
+        let registered = decoder.register_subscription(Subscription {
+            channel_id,
+            start_time: 0,
+            end_time: if matches!(channel_id, 10 | 12 | 14 | 16) {
+                50
+            } else {
+                5000
+            },
+            channel_key: [0; 32],
+            issued: 1,
+        });
+        assert!(registered.is_ok());
+    }
+
+    let subscription = gen_subscription(&secrets, decoder_id, 200, 5000, 2).unwrap();
+    let script = [message(b'S', &subscription), ack(), ack()].concat();
+    let mut console = DecoderConsole(ScriptedTransport::new(&script));
+
+    assert!(run_command(
+        &mut console,
+        &mut decoder,
+        &mut NoLed,
+        &mut MockClock::new()
+    )
+    .is_ok());
+
+    let notice = b"Evicted expired subscriptions for channels: 10 12 14 16";
+    let expected = [ack(), ack(), message(b'G', notice), message(b'S', &[])].concat();
+    assert_eq!(console.0.output, expected);
+    assert!(decoder.get_subscription(2).is_some());
+}
+
+#[test]
+fn generated_unsubscribe_revokes_the_subscription() {
+    let secrets = Secrets::from_json(&deployment_secrets()).unwrap();
+    let decoder_id = parse_decoder_id(env!("DECODER_ID")).unwrap();
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+
+        let subscribe_1 = gen_subscription(&secrets, decoder_id, 5, 50, 1).unwrap();
+        let subscribe_2 = gen_subscription(&secrets, decoder_id, 5, 50, 2).unwrap();
+        let unsubscribe_1 = gen_unsubscribe(&secrets, decoder_id, 1).unwrap();
+        let script = [
+            message(b'S', &subscribe_1),
+            ack(),
+            message(b'S', &subscribe_2),
+            ack(),
+            message(b'U', &unsubscribe_1),
+            ack(),
+        ]
+        .concat();
+        let mut console = DecoderConsole(ScriptedTransport::new(&script));
+        for _ in 0..3 {
+            assert!(run_command(
+                &mut console,
+                &mut decoder,
+                &mut NoLed,
+                &mut MockClock::new()
+            )
+            .is_ok());
+        }
+        assert!(console.0.output.ends_with(&message(b'U', &[])));
+
+        // There's nothing left to revoke the second time.
+        let update = gen_unsubscribe(&secrets, decoder_id, 1).unwrap();
+        let mut console = DecoderConsole(ScriptedTransport::new(&message(b'U', &update)));
+        let result = run_command(
+            &mut console,
+            &mut decoder,
+            &mut NoLed,
+            &mut MockClock::new(),
+        );
+        assert!(matches!(result, Err(DecoderError::NoSubscription)));
+
+        // Replaying the old subscription doesn't bring the channel back.
+        let mut console = DecoderConsole(ScriptedTransport::new(&message(b'S', &subscribe_1)));
+        let result = run_command(
+            &mut console,
+            &mut decoder,
+            &mut NoLed,
+            &mut MockClock::new(),
+        );
+        assert!(matches!(result, Err(DecoderError::StaleUpdate)));
+
+        // An update for another decoder doesn't decrypt.
+        let update = gen_unsubscribe(&secrets, decoder_id ^ 1, 2).unwrap();
+        let mut console = DecoderConsole(ScriptedTransport::new(&message(b'U', &update)));
+        let result = run_command(
+            &mut console,
+            &mut decoder,
+            &mut NoLed,
+            &mut MockClock::new(),
+        );
+        assert!(matches!(result, Err(DecoderError::FailedDecryption)));
+    }
+
+    // The removal was saved.
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    let decoder = Decoder::new(&mut storage).unwrap();
+    assert!(decoder.get_subscription(1).is_none());
+    assert!(decoder.get_subscription(2).is_some());
+}
+
+#[test]
+fn generated_key_rotation_moves_the_decoder_to_the_new_key() {
+    let secrets = Secrets::from_json(&deployment_secrets()).unwrap();
+    let decoder_id = parse_decoder_id(env!("DECODER_ID")).unwrap();
+    let old_encoder = Encoder::new(&secrets.to_json()).unwrap();
+
+    let new_key = SigningKey::from_bytes(&[7; 32]);
+    let mut rotated = Secrets::from_json(&secrets.to_json()).unwrap();
+    rotated.signing_sk = new_key.to_bytes();
+    rotated.signing_key_id = 5;
+    let new_encoder = Encoder::new(&rotated.to_json()).unwrap();
+
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+
+        let mut run = |body: &[u8], opcode| {
+            let script = [message(opcode, body), ack(), ack()].concat();
+            let mut console = DecoderConsole(ScriptedTransport::new(&script));
+            let result = run_command(
+                &mut console,
+                &mut decoder,
+                &mut NoLed,
+                &mut MockClock::new(),
+            );
+            (result, console.0.output)
+        };
+
+        // An update for another decoder doesn't decrypt.
+        let new_pk = new_key.verifying_key().to_bytes();
+        let update = gen_key_rotation(&secrets, decoder_id ^ 1, 1, 5, &new_pk).unwrap();
+        let (result, _) = run(&update, b'R');
+        assert!(matches!(result, Err(DecoderError::FailedDecryption)));
+
+        let update = gen_key_rotation(&secrets, decoder_id, 1, 5, &new_pk).unwrap();
+        let (result, output) = run(&update, b'R');
+        assert!(result.is_ok());
+        assert!(output.ends_with(&message(b'R', &[])));
+
+        // The key that signed the rotation is retired by it.
+        let packet = old_encoder.encode(0, b"old key", 1).unwrap();
+        let (result, _) = run(&packet, b'D');
+        assert!(matches!(result, Err(DecoderError::UnknownSigningKey)));
+        let (result, _) = run(&update, b'R');
+        assert!(matches!(result, Err(DecoderError::UnknownSigningKey)));
+
+        let packet = new_encoder.encode(0, b"new key", 2).unwrap();
+        let (result, _) = run(&packet, b'D');
+        assert!(result.is_ok());
+
+        // The new key can't put the old one back without a higher counter.
+        let old_pk = secrets.signing_key().verifying_key().to_bytes();
+        let update = gen_key_rotation(&rotated, decoder_id, 1, 0, &old_pk).unwrap();
+        let (result, _) = run(&update, b'R');
+        assert!(matches!(result, Err(DecoderError::StaleKeyRotation)));
+    }
+
+    // The rotation was saved.
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+    assert_eq!(decoder.verifying_keys().rotation_counter(), 1);
+
+    let timestamp = 3 * TIME_BOUND_STRIDE;
+    let packet = old_encoder.encode(0, b"old key", timestamp).unwrap();
+    let (header, mut payload) = split_packet(&packet);
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert!(matches!(decoded, Err(DecoderError::UnknownSigningKey)));
+
+    let packet = new_encoder.encode(0, b"new key", timestamp).unwrap();
+    let (header, mut payload) = split_packet(&packet);
+    let decoded = decoder.decode_frame(&header, &mut payload);
+    assert_eq!(decoded.ok(), Some(&b"new key"[..]));
+}
@@ -0,0 +1,359 @@
+//! Cuts the power at every point in a storage write, then boots the decoder
+//! back up from whatever made it to flash.
+//!
+//! A change is appended to the log as one sealed record, so one that the power
+//! is cut in the middle of doesn't decrypt and is dropped. Compacting writes a
+//! whole new bank, but boot keeps using the old one until the new one is
+//! committed. These tests hold it to exactly that: the old subscriptions or
+//! the new ones. Never a mix, never nothing, never garbage, never a panic.
+//!
+//! The power is cut both between flash operations and partway through them,
+//! since a write or erase that only half happened leaves different bits
+//! behind than one that never started.
+
+mod common;
+
+use common::TEST_BASE_ADDR;
+use decoder_core::{
+    decoder::{Decoder, Subscription},
+    flash::{
+        sim::{PowerCutFlash, SimEntropy, SimFlash},
+        DecoderStorage, FlashBackend, STORAGE_MAX, STORAGE_PAGES,
+    },
+};
+use decoder_protocol::KeyRotationBody;
+
+fn subscription(channel_id: u32, end_time: u64) -> Subscription {
+    Subscription {
+        channel_id,
+        start_time: 10,
+        end_time,
+        channel_key: [channel_id as u8; 32],
+        issued: 1,
+    }
+}
+
+/// A flash image with channels 1 and 2 subscribed.
+fn populated_image() -> Vec<u8> {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        assert!(decoder.register_subscription(subscription(1, 100)).is_ok());
+        assert!(decoder.register_subscription(subscription(2, 100)).is_ok());
+    }
+
+    let mut image = vec![0; SimFlash::<STORAGE_PAGES>::SIZE];
+    flash.save(&mut image).unwrap();
+    image
+}
+
+fn flash_from(image: &[u8]) -> SimFlash<STORAGE_PAGES> {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    flash.load(image).unwrap();
+    flash
+}
+
+/// Every place to cut the power in `total` operations, as how many of them
+/// finished and whether the next one was torn partway through. A torn
+/// operation never counts as finished, so tearing the last one leaves the old
+/// state.
+fn every_cut(total: usize) -> impl Iterator<Item = (usize, bool)> {
+    let between = (0..=total).map(|cut| (cut, false));
+    let torn = (0..total).map(|cut| (cut, true));
+    between.chain(torn)
+}
+
+/// Wraps `flash` to cut the power the way [`every_cut`] said to.
+fn cut_flash<F: FlashBackend>(flash: F, cut: usize, torn: bool) -> PowerCutFlash<F> {
+    if torn {
+        PowerCutFlash::tear_after(flash, cut)
+    } else {
+        PowerCutFlash::cut_after(flash, cut)
+    }
+}
+
+/// Boots a decoder from the flash and returns the subscriptions it has.
+fn boot(flash: &mut SimFlash<STORAGE_PAGES>, seed: u64) -> Vec<Subscription> {
+    let mut storage = DecoderStorage::init(flash, SimEntropy::new(seed), TEST_BASE_ADDR)
+        .expect("the decoder should always boot");
+    let decoder = Decoder::new(&mut storage).unwrap();
+    decoder
+        .get_subscriptions()
+        .iter()
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Runs `update` against the image once without cutting the power, to find
+/// out what the new state is and how many flash operations it takes, and then
+/// again with the power cut after and partway through each of those
+/// operations in turn. After every cut the decoder is booted back up and
+/// `check` is given how many operations finished, the total, and what it came
+/// back with.
+fn replay_every_cut(
+    image: &[u8],
+    update: impl Fn(&mut Decoder<&mut PowerCutFlash<&mut SimFlash<STORAGE_PAGES>>, SimEntropy>),
+    check: impl Fn(usize, usize, &[Subscription]),
+) {
+    let total = {
+        let mut flash = flash_from(image);
+        let mut counting = PowerCutFlash::new(&mut flash);
+        let mut storage =
+            DecoderStorage::init(&mut counting, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+        update(&mut Decoder::new(&mut storage).unwrap());
+        drop(storage);
+        counting.operations()
+    };
+    assert!(total > 0, "the update should have written something");
+
+    for (cut, torn) in every_cut(total) {
+        let mut flash = flash_from(image);
+        {
+            let mut cutting = cut_flash(&mut flash, cut, torn);
+            let mut storage =
+                DecoderStorage::init(&mut cutting, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+            update(&mut Decoder::new(&mut storage).unwrap());
+        }
+
+        let subscriptions = boot(&mut flash, 3);
+        check(cut, total, &subscriptions);
+
+        // Whatever state we came back in, the decoder should still be able to
+        // save and load subscriptions.
+        {
+            let mut storage =
+                DecoderStorage::init(&mut flash, SimEntropy::new(4), TEST_BASE_ADDR).unwrap();
+            let mut decoder = Decoder::new(&mut storage).unwrap();
+            assert!(decoder.register_subscription(subscription(4, 400)).is_ok());
+        }
+        let after = boot(&mut flash, 5);
+        assert!(
+            after.contains(&subscription(4, 400)),
+            "cut {cut}/{total}, torn: {torn}"
+        );
+    }
+}
+
+/// Checks that a cut comes back with the new state if the write had
+/// completely finished, and the old state otherwise.
+fn check_old_or_new<'a>(
+    old: &'a [Subscription],
+    new: &'a [Subscription],
+) -> impl Fn(usize, usize, &[Subscription]) + 'a {
+    move |cut, total, subscriptions| {
+        // Subscriptions don't print their keys, so just say which channels
+        // were there.
+        let channels: Vec<_> = subscriptions.iter().map(|sub| sub.channel_id).collect();
+
+        let expected = if cut == total { new } else { old };
+        assert!(
+            subscriptions == expected,
+            "cut {cut}/{total} came back with channels {channels:?}"
+        );
+    }
+}
+
+#[test]
+fn adding_a_subscription() {
+    let old = [subscription(1, 100), subscription(2, 100)];
+    let new = [
+        subscription(1, 100),
+        subscription(2, 100),
+        subscription(3, 100),
+    ];
+
+    replay_every_cut(
+        &populated_image(),
+        |decoder| {
+            let _ = decoder.register_subscription(subscription(3, 100));
+        },
+        check_old_or_new(&old, &new),
+    );
+}
+
+#[test]
+fn replacing_a_subscription() {
+    let old = [subscription(1, 100), subscription(2, 100)];
+    let replacement = Subscription {
+        issued: 2,
+        ..subscription(1, 5000)
+    };
+    let new = [replacement.clone(), subscription(2, 100)];
+
+    replay_every_cut(
+        &populated_image(),
+        |decoder| {
+            let _ = decoder.register_subscription(replacement.clone());
+        },
+        check_old_or_new(&old, &new),
+    );
+}
+
+#[test]
+fn compacting_a_full_log() {
+    // Replace channel 1 until the next replacement won't fit in the bank.
+    let mut flash = flash_from(&populated_image());
+    let mut issued = 1;
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        let mut record_size = 0;
+
+        while decoder.storage_used() as usize + record_size <= STORAGE_MAX {
+            let used = decoder.storage_used() as usize;
+            issued += 1;
+            assert!(decoder
+                .register_subscription(Subscription {
+                    issued,
+                    ..subscription(1, 100)
+                })
+                .is_ok());
+            record_size = decoder.storage_used() as usize - used;
+        }
+    }
+    let mut image = vec![0; SimFlash::<STORAGE_PAGES>::SIZE];
+    flash.save(&mut image).unwrap();
+
+    let old = [
+        Subscription {
+            issued,
+            ..subscription(1, 100)
+        },
+        subscription(2, 100),
+    ];
+    let replacement = Subscription {
+        issued: issued + 1,
+        ..subscription(1, 5000)
+    };
+    let new = [replacement.clone(), subscription(2, 100)];
+
+    replay_every_cut(
+        &image,
+        |decoder| {
+            let _ = decoder.register_subscription(replacement.clone());
+        },
+        check_old_or_new(&old, &new),
+    );
+}
+
+#[test]
+fn first_boot() {
+    let mut blank = vec![0; SimFlash::<STORAGE_PAGES>::SIZE];
+    SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR)
+        .save(&mut blank)
+        .unwrap();
+
+    // Initializing blank flash is itself a write, so the update here is
+    // nothing at all.
+    replay_every_cut(
+        &blank,
+        |_| {},
+        |cut, total, subscriptions| {
+            assert!(subscriptions.is_empty(), "cut {cut}/{total}");
+        },
+    );
+}
+
+#[test]
+fn cut_counts_are_exact() {
+    let mut flash = flash_from(&populated_image());
+    let mut cutting = PowerCutFlash::cut_after(&mut flash, 1);
+    let mut storage =
+        DecoderStorage::init(&mut cutting, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    // The write "succeeds" from the decoder's point of view.
+    assert!(decoder.register_subscription(subscription(3, 100)).is_ok());
+    drop(storage);
+
+    assert!(cutting.is_cut());
+    assert_eq!(cutting.operations(), 1);
+}
+
+#[test]
+fn time_bound_moving_to_the_next_page() {
+    // One page of the log, exactly full, so the next bound needs an erase.
+    let mut image = vec![0; SimFlash::<STORAGE_PAGES>::SIZE];
+    {
+        let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        for bound in 1..=512 {
+            storage.record_time_bound(bound).unwrap();
+        }
+        drop(storage);
+        flash.save(&mut image).unwrap();
+    }
+
+    let total = 2;
+    for (cut, torn) in every_cut(total) {
+        let mut flash = flash_from(&image);
+        {
+            let mut cutting = cut_flash(&mut flash, cut, torn);
+            let mut storage =
+                DecoderStorage::init(&mut cutting, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+            storage.record_time_bound(1000).unwrap();
+            drop(storage);
+            assert_eq!(cutting.operations(), cut + torn as usize);
+        }
+
+        let storage = DecoderStorage::init(&mut flash, SimEntropy::new(3), TEST_BASE_ADDR).unwrap();
+        let expected = if cut == total { 1000 } else { 512 };
+        assert_eq!(
+            storage.time_bound(),
+            Some(expected),
+            "cut {cut}/{total}, torn: {torn}"
+        );
+    }
+}
+
+fn key_rotation(counter: u32) -> KeyRotationBody {
+    KeyRotationBody {
+        counter,
+        key_id: 1,
+        verifying_key: [counter as u8; 32],
+    }
+}
+
+#[test]
+fn key_rotation_moving_to_the_next_page() {
+    // One page of the key log, exactly full, so the next rotation needs an
+    // erase.
+    let mut image = vec![0; SimFlash::<STORAGE_PAGES>::SIZE];
+    {
+        let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        for counter in 1..=102 {
+            storage.record_key_rotation(&key_rotation(counter)).unwrap();
+        }
+        drop(storage);
+        flash.save(&mut image).unwrap();
+    }
+
+    // The erase, then the entry's five blocks.
+    let total = 6;
+    for (cut, torn) in every_cut(total) {
+        let mut flash = flash_from(&image);
+        {
+            let mut cutting = cut_flash(&mut flash, cut, torn);
+            let mut storage =
+                DecoderStorage::init(&mut cutting, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+            storage.record_key_rotation(&key_rotation(1000)).unwrap();
+            drop(storage);
+            assert_eq!(cutting.operations(), cut + torn as usize);
+        }
+
+        let storage = DecoderStorage::init(&mut flash, SimEntropy::new(3), TEST_BASE_ADDR).unwrap();
+        let expected = if cut == total { 1000 } else { 102 };
+        assert_eq!(
+            storage.key_rotation(),
+            Some(&key_rotation(expected)),
+            "cut {cut}/{total}, torn: {torn}"
+        );
+    }
+}
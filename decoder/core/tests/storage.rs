@@ -0,0 +1,980 @@
+//! Tests for `DecoderStorage` and `Decoder` persistence, run against the
+//! simulated flash.
+
+mod common;
+
+use chacha20poly1305::{aead::AeadMutInPlace, KeyInit, XChaCha20Poly1305};
+use common::TEST_BASE_ADDR;
+use decoder_core::{
+    decoder::{Decoder, Subscription, MAX_SUBSCRIPTION_COUNT, RECORD_MAX},
+    flash::{
+        sim::{SimEntropy, SimFlash},
+        BootOutcome, DecoderStorage, DecoderStorageReadError, FlashBackend, FlashError, BANK_PAGES,
+        FLASH_PAGE_SIZE, STORAGE_FORMAT_VERSION, STORAGE_MAX, STORAGE_PAGES,
+    },
+    host_comms::DecoderError,
+};
+use decoder_protocol::KeyRotationBody;
+
+/// Where the second bank starts.
+const SECOND_BANK: u32 = TEST_BASE_ADDR + BANK_PAGES as u32 * FLASH_PAGE_SIZE;
+
+/// The first record starts after the bank header, and its ciphertext after
+/// the record header.
+const FIRST_CIPHERTEXT: u32 = 32 + 48;
+
+/// How many subscriptions it takes to fill the decoder.
+const FULL: u32 = MAX_SUBSCRIPTION_COUNT as u32;
+
+/// Clears one bit of the word at `address`.
+fn clear_a_bit(flash: &mut SimFlash<STORAGE_PAGES>, address: u32) {
+    let word = flash.read_32(address).unwrap();
+    let flipped = word & !(word & word.wrapping_neg());
+    flash.write_32(address, flipped).unwrap();
+}
+
+fn subscription(channel_id: u32) -> Subscription {
+    Subscription {
+        channel_id,
+        start_time: 10,
+        end_time: 1000,
+        channel_key: [channel_id as u8; 32],
+        issued: 1,
+    }
+}
+
+/// Every record in the log, oldest first.
+fn records<F: FlashBackend>(storage: &mut DecoderStorage<F, SimEntropy>) -> Vec<Vec<u8>> {
+    let mut records = Vec::new();
+    storage.replay(|record| records.push(record.to_vec()));
+    records
+}
+
+fn append<F: FlashBackend>(storage: &mut DecoderStorage<F, SimEntropy>, record: &[u8]) -> bool {
+    storage.get_buf_mut().extend_from_slice(record).unwrap();
+    let appended = storage.append_buffer().unwrap();
+    assert!(storage.get_buf_mut().is_empty());
+    appended
+}
+
+#[test]
+fn blank_flash_initializes_empty() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+
+    assert!(records(&mut storage).is_empty());
+    drop(storage);
+
+    // The magic should have been written out so we don't reset next boot.
+    assert_eq!(flash.read_32(TEST_BASE_ADDR).unwrap(), 0x4d696b76);
+}
+
+#[test]
+fn appended_records_survive_reinit() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let contents: Vec<Vec<u8>> = vec![(0..=100).collect(), vec![], vec![0xAA; RECORD_MAX]];
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        for record in &contents {
+            assert!(append(&mut storage, record));
+        }
+        assert_eq!(records(&mut storage), contents);
+    }
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), contents);
+
+    // And more can go on the end.
+    assert!(append(&mut storage, &[1, 2, 3]));
+    drop(storage);
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(3), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage).len(), 4);
+}
+
+#[test]
+fn tampered_record_ends_the_log() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        assert!(append(&mut storage, &[0xAA; 64]));
+        assert!(append(&mut storage, &[0xBB; 64]));
+        assert!(append(&mut storage, &[0xCC; 64]));
+    }
+
+    // Clear a bit in the second record's ciphertext, which is always
+    // possible without an erase. The record after it still checks out, but
+    // follows on from a change that is gone.
+    let second_ciphertext = FIRST_CIPHERTEXT + 64 + 48;
+    clear_a_bit(&mut flash, TEST_BASE_ADDR + second_ciphertext);
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), vec![vec![0xAA; 64]]);
+    assert!(!append(&mut storage, &[0xDD; 64]));
+}
+
+/// Puts [0xAA, 0xAB] in the first bank, then compacts into the second bank
+/// as [0xBB, 0xCC].
+fn compacted_flash() -> SimFlash<STORAGE_PAGES> {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+    assert!(append(&mut storage, &[0xAA; 64]));
+    assert!(append(&mut storage, &[0xAB; 64]));
+    storage.begin_compaction().unwrap();
+    assert!(append(&mut storage, &[0xBB; 64]));
+    assert!(append(&mut storage, &[0xCC; 64]));
+    storage.finish_compaction().unwrap();
+    drop(storage);
+    flash
+}
+
+#[test]
+fn tampered_log_never_goes_back_to_the_older_bank() {
+    let mut flash = compacted_flash();
+    let second_ciphertext = FIRST_CIPHERTEXT + 64 + 48;
+    clear_a_bit(&mut flash, SECOND_BANK + second_ciphertext);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+        assert_eq!(records(&mut storage), vec![vec![0xBB; 64]]);
+
+        // Compacting replaces the older bank, and takes over from the
+        // tampered one.
+        storage.begin_compaction().unwrap();
+        assert!(append(&mut storage, &[0xBB; 64]));
+        assert!(append(&mut storage, &[0xDD; 64]));
+        storage.finish_compaction().unwrap();
+    }
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(3), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), vec![vec![0xBB; 64], vec![0xDD; 64]]);
+}
+
+#[test]
+fn tampered_first_record_leaves_an_empty_log() {
+    let mut flash = compacted_flash();
+    clear_a_bit(&mut flash, SECOND_BANK + FIRST_CIPHERTEXT);
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert!(records(&mut storage).is_empty());
+}
+
+#[test]
+fn corrupted_bank_header_never_goes_back_to_the_older_bank() {
+    // The generation no longer matches its complement, but the bank was
+    // committed, and its log checks out as the one after the first bank's.
+    let mut flash = compacted_flash();
+    clear_a_bit(&mut flash, SECOND_BANK + 8);
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), vec![vec![0xBB; 64], vec![0xCC; 64]]);
+}
+
+#[test]
+fn corrupted_older_bank_header_is_passed_over() {
+    let mut flash = compacted_flash();
+    clear_a_bit(&mut flash, TEST_BASE_ADDR + 8);
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), vec![vec![0xBB; 64], vec![0xCC; 64]]);
+}
+
+#[test]
+fn both_bank_headers_corrupted_wipes_the_log() {
+    let mut flash = compacted_flash();
+    clear_a_bit(&mut flash, TEST_BASE_ADDR + 8);
+    clear_a_bit(&mut flash, SECOND_BANK + 8);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+        assert_eq!(storage.boot_outcome(), BootOutcome::Reset);
+        assert!(records(&mut storage).is_empty());
+        assert!(append(&mut storage, &[0xDD; 64]));
+    }
+
+    // The empty log took over from both of them.
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(3), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), vec![vec![0xDD; 64]]);
+}
+
+/// Flash that can be read and written, but that refuses to erase.
+struct LockedPages(SimFlash<STORAGE_PAGES>);
+
+impl FlashBackend for LockedPages {
+    fn read_32(&self, address: u32) -> Result<u32, FlashError> {
+        self.0.read_32(address)
+    }
+
+    fn read_128(&self, address: u32) -> Result<[u32; 4], FlashError> {
+        self.0.read_128(address)
+    }
+
+    fn write_32(&mut self, address: u32, data: u32) -> Result<(), FlashError> {
+        self.0.write_32(address, data)
+    }
+
+    fn write_128(&mut self, address: u32, data: &[u32; 4]) -> Result<(), FlashError> {
+        self.0.write_128(address, data)
+    }
+
+    unsafe fn erase_page(&mut self, _address: u32) -> Result<(), FlashError> {
+        Err(FlashError::AccessViolation)
+    }
+}
+
+#[test]
+fn failing_to_reset_is_an_error_not_a_panic() {
+    let flash = LockedPages(SimFlash::new(TEST_BASE_ADDR));
+    let result = DecoderStorage::init(flash, SimEntropy::new(1), TEST_BASE_ADDR);
+    assert!(matches!(result, Err(DecoderStorageReadError::FlashError)));
+}
+
+/// Flash that can be read, but that refuses to be written or erased.
+struct WriteProtected(SimFlash<STORAGE_PAGES>);
+
+impl FlashBackend for WriteProtected {
+    fn read_32(&self, address: u32) -> Result<u32, FlashError> {
+        self.0.read_32(address)
+    }
+
+    fn read_128(&self, address: u32) -> Result<[u32; 4], FlashError> {
+        self.0.read_128(address)
+    }
+
+    fn write_32(&mut self, _address: u32, _data: u32) -> Result<(), FlashError> {
+        Err(FlashError::AccessViolation)
+    }
+
+    fn write_128(&mut self, _address: u32, _data: &[u32; 4]) -> Result<(), FlashError> {
+        Err(FlashError::AccessViolation)
+    }
+
+    unsafe fn erase_page(&mut self, _address: u32) -> Result<(), FlashError> {
+        Err(FlashError::AccessViolation)
+    }
+}
+
+#[test]
+fn changes_that_fail_to_save_are_not_made() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        assert!(decoder.register_subscription(subscription(1)).is_ok());
+    }
+
+    let flash = WriteProtected(flash);
+    let mut storage = DecoderStorage::init(flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    let replacement = Subscription {
+        issued: 2,
+        ..subscription(1)
+    };
+    assert!(matches!(
+        decoder.register_subscription(Subscription {
+            channel_key: [0xAA; 32],
+            ..replacement.clone()
+        }),
+        Err(DecoderError::SavingFailed)
+    ));
+    assert!(matches!(
+        decoder.register_subscription(subscription(2)),
+        Err(DecoderError::SavingFailed)
+    ));
+    assert!(matches!(
+        decoder.remove_subscription(1, 10),
+        Err(DecoderError::SavingFailed)
+    ));
+
+    assert!(decoder.get_subscription(1) == Some(&subscription(1)));
+    assert!(decoder.get_subscription(2).is_none());
+    // The unsubscribe didn't raise the issue time floor, so an update issued
+    // before it still gets as far as saving.
+    assert!(matches!(
+        decoder.register_subscription(replacement),
+        Err(DecoderError::SavingFailed)
+    ));
+}
+
+/// Writes `bytes` to erased flash at `address`, padded out with 0xFF.
+fn write_bytes(flash: &mut SimFlash<STORAGE_PAGES>, address: u32, bytes: &[u8]) {
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let mut block = [0xFF; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let words = core::array::from_fn(|word| {
+            u32::from_ne_bytes(block[4 * word..4 * word + 4].try_into().unwrap())
+        });
+        flash.write_128(address + 16 * i as u32, &words).unwrap();
+    }
+}
+
+/// The flash key that the old firmware's build made up, which this build was
+/// given. Without it, nothing that firmware saved can be read.
+fn legacy_flash_key() -> Option<[u8; 32]> {
+    let mut key = [0; 32];
+    hex::decode_to_slice(env!("DECODER_LEGACY_FLASH_KEY"), &mut key).ok()?;
+    Some(key)
+}
+
+/// Postcard's varint encoding.
+fn varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Lays out storage the way that version 1 firmware did: one blob at the
+/// start of the storage, holding its table of 8 subscriptions, sealed with
+/// `key`.
+fn write_version_1_blob(
+    flash: &mut SimFlash<STORAGE_PAGES>,
+    key: &[u8; 32],
+    subs: &[Subscription],
+) {
+    let mut table = Vec::new();
+    for slot in 0..8 {
+        let Some(sub) = subs.get(slot) else {
+            table.push(0);
+            continue;
+        };
+        table.push(1);
+        varint(sub.channel_id as u64, &mut table);
+        varint(sub.start_time, &mut table);
+        varint(sub.end_time, &mut table);
+        table.extend(sub.channel_key);
+    }
+
+    let nonce = [7; 24];
+    let tag = XChaCha20Poly1305::new(key.into())
+        .encrypt_in_place_detached(&nonce.into(), &[], &mut table)
+        .unwrap();
+
+    let mut header = vec![0xFF; 4];
+    header.extend((table.len() as u32).to_ne_bytes());
+    header.extend(nonce);
+    header.extend(tag);
+    write_bytes(flash, TEST_BASE_ADDR, &header);
+    write_bytes(flash, TEST_BASE_ADDR + 48, &table);
+    // The magic went in last.
+    flash.write_32(TEST_BASE_ADDR, 0x4d696b75).unwrap();
+}
+
+#[test]
+fn version_1_blob_is_migrated() {
+    let Some(key) = legacy_flash_key() else {
+        return;
+    };
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    // Version 1 had no issue times, so they come over as 0.
+    let old = [
+        Subscription {
+            end_time: 1_000_000,
+            issued: 0,
+            ..subscription(1)
+        },
+        Subscription {
+            issued: 0,
+            ..subscription(3)
+        },
+    ];
+    write_version_1_blob(&mut flash, &key, &old);
+
+    // A compaction that the power is cut in the middle of leaves the blob
+    // where it was.
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        assert_eq!(storage.boot_outcome(), BootOutcome::Loaded { version: 1 });
+        storage.begin_compaction().unwrap();
+    }
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+        assert_eq!(storage.boot_outcome(), BootOutcome::Loaded { version: 1 });
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        assert!(decoder.get_subscription(1) == Some(&old[0]));
+        assert!(decoder.get_subscription(3) == Some(&old[1]));
+        assert_eq!(decoder.storage_version(), 1);
+
+        // Any update made since replaces them, and the next change rewrites
+        // the log in the current version.
+        assert!(decoder.register_subscription(subscription(1)).is_ok());
+        assert_eq!(decoder.storage_version(), STORAGE_FORMAT_VERSION);
+    }
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(3), TEST_BASE_ADDR).unwrap();
+    assert_eq!(
+        storage.boot_outcome(),
+        BootOutcome::Loaded {
+            version: STORAGE_FORMAT_VERSION
+        }
+    );
+    let decoder = Decoder::new(&mut storage).unwrap();
+    assert!(decoder.get_subscription(1) == Some(&subscription(1)));
+    assert!(decoder.get_subscription(3) == Some(&old[1]));
+}
+
+#[test]
+fn version_1_blob_needs_the_old_flash_key() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let wrong_key = legacy_flash_key().map_or([0; 32], |key| key.map(|byte| !byte));
+    write_version_1_blob(&mut flash, &wrong_key, &[subscription(1)]);
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+    assert_eq!(storage.boot_outcome(), BootOutcome::Reset);
+    let decoder = Decoder::new(&mut storage).unwrap();
+    assert!(decoder.get_subscriptions().iter().all(Option::is_none));
+}
+
+#[test]
+fn newer_version_is_left_alone() {
+    let newer = STORAGE_FORMAT_VERSION + 1;
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    flash
+        .write_128(TEST_BASE_ADDR, &[0x4d696b76, newer, 1, !1])
+        .unwrap();
+    write_bytes(&mut flash, TEST_BASE_ADDR + 16, &[0x55; 100]);
+
+    let mut before = vec![0; SimFlash::<STORAGE_PAGES>::SIZE];
+    flash.save(&mut before).unwrap();
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        assert_eq!(storage.boot_outcome(), BootOutcome::NewerVersion(newer));
+        // The timestamp and key logs have their own layouts, so they're
+        // still kept up.
+        storage.record_time_bound(5).unwrap();
+        storage.record_key_rotation(&key_rotation(1)).unwrap();
+
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        assert!(decoder.get_subscriptions().iter().all(Option::is_none));
+        assert_eq!(decoder.storage_version(), newer);
+        assert!(matches!(
+            decoder.register_subscription(subscription(1)),
+            Err(DecoderError::StorageFromNewerFirmware)
+        ));
+        // It wasn't saved, so it can't be used either.
+        assert!(decoder.get_subscription(1).is_none());
+    }
+
+    let mut after = vec![0; SimFlash::<STORAGE_PAGES>::SIZE];
+    flash.save(&mut after).unwrap();
+    let banks = 2 * STORAGE_MAX;
+    assert!(before[..banks] == after[..banks], "the banks were changed");
+
+    let storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(storage.time_bound(), Some(5));
+    assert_eq!(storage.key_rotation(), Some(&key_rotation(1)));
+}
+
+#[test]
+fn torn_last_record_is_dropped() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        assert!(append(&mut storage, &[0xAA; 64]));
+        assert!(append(&mut storage, &[0xBB; 64]));
+    }
+
+    // The last record looks the same as one that the power was cut in the
+    // middle of writing.
+    let second_ciphertext = FIRST_CIPHERTEXT + 64 + 48;
+    clear_a_bit(&mut flash, TEST_BASE_ADDR + second_ciphertext);
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), vec![vec![0xAA; 64]]);
+
+    // Nothing can go after it until the log is compacted.
+    assert!(!append(&mut storage, &[0xCC; 16]));
+    storage.begin_compaction().unwrap();
+    assert!(append(&mut storage, &[0xAA; 64]));
+    assert!(append(&mut storage, &[0xCC; 16]));
+    storage.finish_compaction().unwrap();
+    drop(storage);
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(3), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), vec![vec![0xAA; 64], vec![0xCC; 16]]);
+}
+
+#[test]
+fn full_bank_is_compacted_into_the_other() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut appended = 0;
+        while append(&mut storage, &[appended as u8; 100]) {
+            appended += 1;
+        }
+        assert_eq!(appended, (STORAGE_MAX - 32) / (48 + 112));
+        assert!(storage.stored_len() as usize <= STORAGE_MAX);
+
+        storage.begin_compaction().unwrap();
+        assert!(append(&mut storage, &[0x55; 100]));
+        storage.finish_compaction().unwrap();
+        assert_eq!(storage.stored_len(), 32 + 48 + 112);
+    }
+
+    // The first bank is still there, but the second one is newer.
+    assert_eq!(flash.read_32(TEST_BASE_ADDR).unwrap(), 0x4d696b76);
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), vec![vec![0x55; 100]]);
+}
+
+#[test]
+fn unfinished_compaction_keeps_the_old_log() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        assert!(append(&mut storage, &[0xAA; 64]));
+        storage.begin_compaction().unwrap();
+        assert!(append(&mut storage, &[0xBB; 64]));
+    }
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(records(&mut storage), vec![vec![0xAA; 64]]);
+}
+
+#[test]
+fn reset_storage_forgets_everything() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        assert!(append(&mut storage, &[0x55; 32]));
+        storage.reset_storage().unwrap();
+    }
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert!(records(&mut storage).is_empty());
+}
+
+#[test]
+fn subscriptions_persist_across_boots() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        assert!(decoder.register_subscription(subscription(1)).is_ok());
+        assert!(decoder.register_subscription(subscription(2)).is_ok());
+
+        // Replacing a channel shouldn't take another slot.
+        let mut replacement = subscription(1);
+        replacement.end_time = 2000;
+        replacement.issued = 2;
+        assert!(decoder.register_subscription(replacement).is_ok());
+    }
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    let decoder = Decoder::new(&mut storage).unwrap();
+    let subscriptions: Vec<_> = decoder.get_subscriptions().iter().flatten().collect();
+
+    assert_eq!(subscriptions.len(), 2);
+    assert_eq!(decoder.get_subscription(1).unwrap().end_time, 2000);
+    assert!(decoder.get_subscription(2) == Some(&subscription(2)));
+}
+
+#[test]
+fn many_changes_compact_the_log() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    // Each replacement is a record of no more than 96 bytes, so this fills
+    // the bank a few times over.
+    let last = 3 * STORAGE_MAX as u64 / 96;
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        assert!(decoder.register_subscription(subscription(2)).is_ok());
+
+        for issued in 2..=last {
+            assert!(decoder
+                .register_subscription(Subscription {
+                    end_time: issued,
+                    issued,
+                    ..subscription(1)
+                })
+                .is_ok());
+            assert!(decoder.storage_used() as usize <= STORAGE_MAX);
+        }
+    }
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+    assert_eq!(decoder.get_subscription(1).unwrap().end_time, last);
+    assert!(decoder.get_subscription(2) == Some(&subscription(2)));
+    assert!(matches!(
+        decoder.register_subscription(Subscription {
+            issued: last,
+            ..subscription(1)
+        }),
+        Err(DecoderError::StaleUpdate)
+    ));
+}
+
+#[test]
+fn subscription_space_runs_out() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    for channel_id in 1..=FULL {
+        assert!(decoder
+            .register_subscription(subscription(channel_id))
+            .is_ok());
+    }
+
+    assert!(decoder
+        .register_subscription(subscription(FULL + 1))
+        .is_err());
+}
+
+#[test]
+fn full_storage_evicts_expired_subscriptions() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        // Frames up to 100 have been decoded, so 3 and 6 are finished.
+        storage.record_time_bound(100).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+
+        for channel_id in 1..=FULL {
+            let end_time = if matches!(channel_id, 3 | 6) {
+                100
+            } else {
+                1000
+            };
+            assert!(decoder
+                .register_subscription(Subscription {
+                    end_time,
+                    ..subscription(channel_id)
+                })
+                .is_ok());
+        }
+
+        let evicted = decoder.register_subscription(subscription(FULL + 1));
+        assert_eq!(evicted.ok().as_deref(), Some(&[3, 6][..]));
+
+        // The space it made is still there for one more. Anything issued
+        // before the evicted subscriptions is refused now, like after an
+        // unsubscribe, so these have to be newer.
+        let evicted = decoder.register_subscription(Subscription {
+            issued: 2,
+            ..subscription(FULL + 2)
+        });
+        assert_eq!(evicted.ok().as_deref(), Some(&[][..]));
+        assert!(matches!(
+            decoder.register_subscription(Subscription {
+                issued: 2,
+                ..subscription(FULL + 3)
+            }),
+            Err(DecoderError::NoMoreSubscriptionSpace)
+        ));
+    }
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+    assert!(decoder.get_subscription(3).is_none());
+    assert!(decoder.get_subscription(6).is_none());
+    assert!(decoder.get_subscription(FULL + 2).is_some());
+
+    // An evicted subscription can't be sent again to take its slot back.
+    assert!(matches!(
+        decoder.register_subscription(Subscription {
+            end_time: 100,
+            ..subscription(3)
+        }),
+        Err(DecoderError::StaleUpdate)
+    ));
+}
+
+#[test]
+fn removed_subscription_frees_its_slot() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        for channel_id in 1..=FULL {
+            assert!(decoder
+                .register_subscription(subscription(channel_id))
+                .is_ok());
+        }
+
+        assert!(decoder.remove_subscription(3, 2).is_ok());
+        assert!(decoder.remove_subscription(3, 3).is_err());
+        assert!(decoder
+            .register_subscription(Subscription {
+                issued: 3,
+                ..subscription(FULL + 1)
+            })
+            .is_ok());
+    }
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    let decoder = Decoder::new(&mut storage).unwrap();
+    assert!(decoder.get_subscription(3).is_none());
+    assert!(decoder.get_subscription(FULL + 1).is_some());
+}
+
+#[test]
+fn tampering_after_an_unsubscribe_does_not_undo_it() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let third_record;
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        assert!(decoder.register_subscription(subscription(1)).is_ok());
+
+        // Replace channel 2 until the log is compacted into the second bank,
+        // so that the first bank is left without the unsubscribe.
+        let mut issued = 1;
+        loop {
+            let used = decoder.storage_used();
+            assert!(decoder
+                .register_subscription(Subscription {
+                    issued,
+                    ..subscription(2)
+                })
+                .is_ok());
+            issued += 1;
+            if decoder.storage_used() < used {
+                break;
+            }
+        }
+
+        assert!(decoder.remove_subscription(1, issued).is_ok());
+        third_record = decoder.storage_used();
+        for channel_id in [3, 4] {
+            assert!(decoder
+                .register_subscription(Subscription {
+                    issued: issued + 1,
+                    ..subscription(channel_id)
+                })
+                .is_ok());
+        }
+    }
+
+    // Damage the record after the unsubscribe, the way that anyone who can
+    // clear bits could, so that the bank no longer checks out all the way
+    // through.
+    clear_a_bit(&mut flash, SECOND_BANK + third_record + 48);
+
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+    assert!(decoder.get_subscription(1).is_none());
+    assert!(decoder.get_subscription(2).is_some());
+    assert!(decoder.get_subscription(3).is_none());
+    assert!(decoder.get_subscription(4).is_none());
+    assert!(matches!(
+        decoder.register_subscription(subscription(1)),
+        Err(DecoderError::StaleUpdate)
+    ));
+}
+
+#[test]
+fn old_updates_are_refused_after_a_reboot() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let old = Subscription {
+        issued: 10,
+        ..subscription(1)
+    };
+    let new = Subscription {
+        end_time: 5000,
+        issued: 20,
+        ..subscription(1)
+    };
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        assert!(decoder.register_subscription(old.clone()).is_ok());
+        assert!(decoder.register_subscription(new.clone()).is_ok());
+    }
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+        let mut decoder = Decoder::new(&mut storage).unwrap();
+        assert!(matches!(
+            decoder.register_subscription(old.clone()),
+            Err(DecoderError::StaleUpdate)
+        ));
+        assert!(matches!(
+            decoder.register_subscription(new.clone()),
+            Err(DecoderError::StaleUpdate)
+        ));
+        assert!(matches!(
+            decoder.remove_subscription(1, 15),
+            Err(DecoderError::StaleUpdate)
+        ));
+        assert!(decoder.remove_subscription(1, 30).is_ok());
+    }
+
+    // With the channel gone, the old subscriptions still can't come back.
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(3), TEST_BASE_ADDR).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+    for sub in [old, new] {
+        assert!(matches!(
+            decoder.register_subscription(sub),
+            Err(DecoderError::StaleUpdate)
+        ));
+    }
+    assert!(decoder.get_subscription(1).is_none());
+}
+
+#[test]
+fn removing_a_channel_holds_back_older_updates_for_every_channel() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+    let mut decoder = Decoder::new(&mut storage).unwrap();
+
+    // A batch made at 10 to 12, with channel 1 unsubscribed at 20 before the
+    // rest of the batch is sent.
+    let sub = |channel_id, issued| Subscription {
+        issued,
+        ..subscription(channel_id)
+    };
+    assert!(decoder.register_subscription(sub(1, 10)).is_ok());
+    assert!(decoder.register_subscription(sub(2, 11)).is_ok());
+    assert!(decoder.remove_subscription(1, 20).is_ok());
+
+    // The floor is shared, so the rest of the batch is refused too, even
+    // though it has nothing to do with channel 1.
+    assert!(matches!(
+        decoder.register_subscription(sub(3, 12)),
+        Err(DecoderError::StaleUpdate)
+    ));
+    assert!(matches!(
+        decoder.remove_subscription(2, 19),
+        Err(DecoderError::StaleUpdate)
+    ));
+    // Channel 2 wasn't touched.
+    assert!(decoder.get_subscription(2).is_some());
+
+    // Made again after the unsubscribe, it goes through.
+    assert!(decoder.register_subscription(sub(3, 21)).is_ok());
+}
+
+#[test]
+fn time_bound_survives_reinit() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        assert_eq!(storage.time_bound(), None);
+        storage.record_time_bound(5).unwrap();
+        storage.record_time_bound(u64::MAX - 1).unwrap();
+    }
+
+    let storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(storage.time_bound(), Some(u64::MAX - 1));
+}
+
+#[test]
+fn time_log_moves_between_pages() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    // Enough to fill both pages of the log and come back around to the first.
+    for bound in 1..=1500 {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(bound), TEST_BASE_ADDR).unwrap();
+        assert_eq!(storage.time_bound(), Some(bound - 1).filter(|&b| b > 0));
+        storage.record_time_bound(bound).unwrap();
+    }
+
+    // The subscription log is left alone.
+    assert_eq!(flash.read_32(TEST_BASE_ADDR).unwrap(), 0x4d696b76);
+}
+
+fn key_rotation(counter: u32) -> KeyRotationBody {
+    KeyRotationBody {
+        counter,
+        key_id: counter as u8,
+        verifying_key: [counter as u8; 32],
+    }
+}
+
+#[test]
+fn key_rotation_survives_reinit() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+        assert!(storage.key_rotation().is_none());
+        storage.record_time_bound(5).unwrap();
+        storage.record_key_rotation(&key_rotation(1)).unwrap();
+        storage.record_key_rotation(&key_rotation(7)).unwrap();
+    }
+
+    let storage = DecoderStorage::init(&mut flash, SimEntropy::new(2), TEST_BASE_ADDR).unwrap();
+    assert_eq!(storage.key_rotation(), Some(&key_rotation(7)));
+    // The logs are kept apart.
+    assert_eq!(storage.time_bound(), Some(5));
+}
+
+#[test]
+fn key_log_moves_between_pages() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+
+    // Enough to fill both pages of the log and come back around to the first.
+    for counter in 1..=250 {
+        let mut storage =
+            DecoderStorage::init(&mut flash, SimEntropy::new(counter as u64), TEST_BASE_ADDR)
+                .unwrap();
+        let expected = Some(key_rotation(counter - 1)).filter(|r| r.counter > 0);
+        assert_eq!(storage.key_rotation(), expected.as_ref());
+        storage.record_key_rotation(&key_rotation(counter)).unwrap();
+    }
+
+    // The subscription log is left alone.
+    assert_eq!(flash.read_32(TEST_BASE_ADDR).unwrap(), 0x4d696b76);
+}
+
+#[test]
+fn unusable_saved_rotation_is_an_error_not_a_panic() {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(TEST_BASE_ADDR);
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(1), TEST_BASE_ADDR).unwrap();
+
+    // The identity point, which the decoder refuses to rotate to.
+    let mut identity = [0; 32];
+    identity[0] = 1;
+    storage
+        .record_key_rotation(&KeyRotationBody {
+            verifying_key: identity,
+            ..key_rotation(1)
+        })
+        .unwrap();
+
+    assert!(matches!(
+        Decoder::new(&mut storage),
+        Err(DecoderError::LoadingFailed)
+    ));
+}
@@ -0,0 +1,105 @@
+//! Wrappers that hand the board's peripherals to decoder-core.
+
+use core::cell::Cell;
+
+use cortex_m::{
+    interrupt::{self, Mutex},
+    peripheral::{syst::SystClkSource, SYST},
+};
+use cortex_m_rt::exception;
+use decoder_core::{
+    crypto::EntropySource,
+    flash::{FlashBackend, FlashError, FLASH_PAGE_SIZE, STORAGE_PAGES},
+    timer::Clock,
+};
+use hal::{flc::Flc, trng::Trng};
+use rand::RngCore;
+
+/// The start of the flash that memory.x sets aside for us to persist to. The
+/// storage uses [`decoder_core::flash::STORAGE_PAGES`] pages from here.
+pub const PERSIST_BASE_ADDR: u32 = 0x10044000;
+
+// memory.x gives PERSIST 0x20000 bytes. A build with room for more
+// subscriptions than will fit there needs it made bigger.
+const _: () = assert!(STORAGE_PAGES as u32 * FLASH_PAGE_SIZE <= 0x20000);
+
+/// The flash controller, as a [`FlashBackend`].
+pub struct Flash(pub Flc);
+
+fn convert_flash_error(err: hal::flc::FlashError) -> FlashError {
+    match err {
+        hal::flc::FlashError::InvalidAddress => FlashError::InvalidAddress,
+        hal::flc::FlashError::AccessViolation => FlashError::AccessViolation,
+        hal::flc::FlashError::NeedsErase => FlashError::NeedsErase,
+    }
+}
+
+impl FlashBackend for Flash {
+    fn read_32(&self, address: u32) -> Result<u32, FlashError> {
+        self.0.read_32(address).map_err(convert_flash_error)
+    }
+
+    fn read_128(&self, address: u32) -> Result<[u32; 4], FlashError> {
+        self.0.read_128(address).map_err(convert_flash_error)
+    }
+
+    fn write_32(&mut self, address: u32, data: u32) -> Result<(), FlashError> {
+        self.0.write_32(address, data).map_err(convert_flash_error)
+    }
+
+    fn write_128(&mut self, address: u32, data: &[u32; 4]) -> Result<(), FlashError> {
+        self.0.write_128(address, data).map_err(convert_flash_error)
+    }
+
+    unsafe fn erase_page(&mut self, address: u32) -> Result<(), FlashError> {
+        self.0.erase_page(address).map_err(convert_flash_error)
+    }
+}
+
+/// The hardware TRNG, as an [`EntropySource`].
+pub struct Entropy(pub Trng);
+
+impl EntropySource for Entropy {
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+}
+
+/// Milliseconds since [`SysTickClock::start`], counted by the SysTick handler.
+static TICKS_MS: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+#[exception]
+fn SysTick() {
+    interrupt::free(|cs| {
+        let ticks = TICKS_MS.borrow(cs);
+        ticks.set(ticks.get() + 1);
+    });
+}
+
+/// SysTick, interrupting once a millisecond, as a [`Clock`].
+pub struct SysTickClock(SYST);
+
+impl SysTickClock {
+    /// Starts SysTick counting, given the frequency of the core clock in Hz.
+    pub fn start(mut syst: SYST, sys_clk_hz: u32) -> Self {
+        syst.set_clock_source(SystClkSource::Core);
+        syst.set_reload(sys_clk_hz / 1000 - 1);
+        syst.clear_current();
+        syst.enable_interrupt();
+        syst.enable_counter();
+        Self(syst)
+    }
+}
+
+impl Clock for SysTickClock {
+    fn now_ms(&self) -> u64 {
+        interrupt::free(|cs| TICKS_MS.borrow(cs).get())
+    }
+
+    fn wait_until(&mut self, deadline_ms: u64) {
+        // SysTick wakes us up every millisecond, so there's no need to spin.
+        while self.now_ms() < deadline_ms {
+            cortex_m::asm::wfi();
+        }
+    }
+}
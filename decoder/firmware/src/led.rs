@@ -1,3 +1,4 @@
+use decoder_core::led::StatusLed;
 use hal::gpio::{InputOutput, Pin};
 
 /// Reprsentation of the RGB LED, giving it some functions to set the color
@@ -39,24 +40,26 @@ impl Led {
             self.led_b.set_high();
         }
     }
+}
 
-    pub fn red(&mut self) {
+impl StatusLed for Led {
+    fn red(&mut self) {
         self.set_lights(true, false, false);
     }
 
-    pub fn green(&mut self) {
+    fn green(&mut self) {
         self.set_lights(false, true, false);
     }
 
-    pub fn cyan(&mut self) {
+    fn cyan(&mut self) {
         self.set_lights(false, true, true);
     }
 
-    pub fn magenta(&mut self) {
+    fn magenta(&mut self) {
         self.set_lights(true, false, true);
     }
 
-    pub fn yellow(&mut self) {
+    fn yellow(&mut self) {
         self.set_lights(true, true, false);
     }
 }
@@ -1,34 +1,32 @@
 #![no_std]
 #![no_main]
-// The only reason that this is unstable is because bikeshedding about the zero
-// case.
-#![feature(array_chunks)]
 
-use crypto::bootstrap_crypto;
-use flash::DecoderStorage;
+use board::{Entropy, Flash, SysTickClock, PERSIST_BASE_ADDR};
+use decoder_core::{
+    cmd_logic,
+    crypto::bootstrap_crypto,
+    decoder::Decoder,
+    flash::DecoderStorage,
+    host_comms::DecoderConsole,
+    led::StatusLed,
+};
 use hal::flc::Flc;
 use hal::icc::Icc;
 use led::Led;
 
 pub extern crate max7800x_hal as hal;
-use decoder::Decoder;
 pub use hal::entry;
 pub use hal::pac;
 
-use host_comms::DecoderConsole;
-
 use panic_halt as _;
 
-mod cmd_logic;
-mod crypto;
-mod decoder;
-mod flash;
-mod host_comms;
+mod board;
 mod led;
 
 #[entry]
 fn main() -> ! {
     let p = pac::Peripherals::take().unwrap();
+    let core = cortex_m::Peripherals::take().unwrap();
 
     // Set the system clock to the IPO
     let mut gcr = hal::gcr::Gcr::new(p.gcr, p.lpgcr);
@@ -67,7 +65,10 @@ fn main() -> ! {
     // Set light red: Initializing
     led.red();
 
-    let flc = Flc::new(p.flc, clks.sys_clk);
+    // Start counting milliseconds, for delaying responses
+    let mut clock = SysTickClock::start(core.SYST, clks.sys_clk.frequency);
+
+    let flc = Flash(Flc::new(p.flc, clks.sys_clk));
 
     // Working with the flash needs the ICC disabled, so we ensure that here
     // We don't need the perfomance boost anyways
@@ -75,12 +76,20 @@ fn main() -> ! {
     icc.disable();
 
     // Create a new TRNG peripheral instance
-    let trng = hal::trng::Trng::new(p.trng, &mut gcr.reg);
+    let trng = Entropy(hal::trng::Trng::new(p.trng, &mut gcr.reg));
 
     // Initialize our types
-    let mut storage = DecoderStorage::init(flc, trng).unwrap();
-    let mut decoder = Decoder::new(&mut storage);
+    // If the saved state can't be read, the LED stays red and every command
+    // gets the error back.
     let mut console = DecoderConsole(uart);
+    let mut storage = match DecoderStorage::init(flc, trng, PERSIST_BASE_ADDR) {
+        Ok(storage) => storage,
+        Err(err) => cmd_logic::refuse_commands(&mut console, err.into()),
+    };
+    let mut decoder = match Decoder::new(&mut storage) {
+        Ok(decoder) => decoder,
+        Err(err) => cmd_logic::refuse_commands(&mut console, err),
+    };
 
     // This preinitializes the VerifyingKey OnceCell, which would
     // otherwise be initialized on the first message received.
@@ -90,8 +99,8 @@ fn main() -> ! {
         // Set light green: Ready!
         led.green();
 
-        if let Err(err) = cmd_logic::run_command(&mut console, &mut decoder, &mut led) {
-            err.write_to_console(&console);
+        if let Err(err) = cmd_logic::run_command(&mut console, &mut decoder, &mut led, &mut clock) {
+            err.write_to_console(&mut console);
         }
     }
 }
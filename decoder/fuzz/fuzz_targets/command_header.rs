@@ -0,0 +1,9 @@
+//! Arbitrary bytes from the host, straight into the command loop.
+#![no_main]
+
+use decoder_fuzz::run_session;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    run_session(data);
+});
@@ -0,0 +1,19 @@
+//! A Decode command with an arbitrary body.
+#![no_main]
+
+use decoder_fuzz::{message, run_session};
+use decoder_protocol::Opcode;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() > u16::MAX as usize {
+        return;
+    }
+
+    let received = run_session(&message(Opcode::Decode, data));
+
+    // Nothing the fuzzer makes is signed, so no frame should come out.
+    assert!(received
+        .iter()
+        .all(|(header, _)| header.opcode != Opcode::Decode));
+});
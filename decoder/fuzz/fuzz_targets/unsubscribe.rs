@@ -0,0 +1,20 @@
+//! An Unsubscribe command with an arbitrary body.
+#![no_main]
+
+use decoder_fuzz::{message, run_session};
+use decoder_protocol::Opcode;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() > u16::MAX as usize {
+        return;
+    }
+
+    let received = run_session(&message(Opcode::Unsubscribe, data));
+
+    // Nothing the fuzzer makes is signed, so nothing should be unsubscribed
+    // from (and there's nothing subscribed to begin with).
+    assert!(received
+        .iter()
+        .all(|(header, _)| header.opcode != Opcode::Unsubscribe));
+});
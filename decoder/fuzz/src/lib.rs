@@ -0,0 +1,235 @@
+//! Shared pieces of the fuzz targets: a simulated host to stand on the other
+//! end of the console, and a decoder session over simulated flash.
+//!
+//! The fuzzer's input is everything that the host sends, except for ACKs.
+//! Those are filled in by [`SimulatedHost`] the way the real host tools do,
+//! so that the fuzzer doesn't waste its time guessing where they go.
+
+use std::collections::VecDeque;
+
+use decoder_core::{
+    cmd_logic::run_command,
+    decoder::{Decoder, MAX_SUBSCRIPTION_COUNT},
+    flash::{
+        sim::{SimEntropy, SimFlash},
+        DecoderStorage, STORAGE_PAGES,
+    },
+    host_comms::{DecoderConsole, DecoderError},
+    led::StatusLed,
+    timer::sim::MockClock,
+};
+use decoder_protocol::{
+    InfoResponse, ListResponse, MessageHeader, Opcode, BLOCK_LEN, MAX_FRAME_LEN,
+};
+
+/// Where the simulated flash page lives. This is the same page the firmware
+/// uses.
+const PERSIST_BASE_ADDR: u32 = 0x10044000;
+
+/// How far through a message from the decoder the host is.
+enum Receiving {
+    Header(Vec<u8>),
+    Body {
+        header: MessageHeader,
+        body: Vec<u8>,
+    },
+}
+
+/// A transport that plays the host's side of the protocol. Bytes from the
+/// fuzzer are sent as they are, and everything the decoder writes is parsed
+/// and ACKed like `ectf25.utils.decoder` would.
+///
+/// Parsing the decoder's output as it goes means that any message the
+/// decoder sends that the host couldn't follow panics straight away.
+pub struct SimulatedHost<'a> {
+    input: &'a [u8],
+    acks: VecDeque<u8>,
+    receiving: Receiving,
+    /// Every complete message that the decoder has sent.
+    pub received: Vec<(MessageHeader, Vec<u8>)>,
+}
+
+impl<'a> SimulatedHost<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            acks: VecDeque::new(),
+            receiving: Receiving::Header(Vec::new()),
+            received: Vec::new(),
+        }
+    }
+
+    fn send_ack(&mut self) {
+        self.acks.extend(MessageHeader::ACK.encode());
+    }
+
+    fn receive_byte(&mut self, byte: u8) {
+        match &mut self.receiving {
+            Receiving::Header(bytes) => {
+                // The decoder always waits for the host to ACK a message
+                // before it starts on the next one.
+                if bytes.is_empty() {
+                    assert!(self.acks.is_empty(), "decoder didn't wait for an ACK");
+                }
+
+                bytes.push(byte);
+                let Ok(header) = <[u8; MessageHeader::SIZE]>::try_from(&bytes[..]) else {
+                    return;
+                };
+
+                let header = MessageHeader::decode(&header)
+                    .unwrap_or_else(|err| panic!("decoder sent a bad header: {err:?}"));
+
+                // The host ACKs every header but ACKs and debug messages.
+                if !matches!(header.opcode, Opcode::Ack | Opcode::Debug) {
+                    self.send_ack();
+                }
+
+                self.receiving = Receiving::Body {
+                    header,
+                    body: Vec::new(),
+                };
+            }
+            Receiving::Body { body, .. } => body.push(byte),
+        }
+
+        let Receiving::Body { header, body } = &self.receiving else {
+            return;
+        };
+        let header = *header;
+        let length = header.length as usize;
+        let received = body.len();
+
+        // ... and every block of the body, including the last one.
+        if header.opcode != Opcode::Debug
+            && received != 0
+            && (received % BLOCK_LEN == 0 || received == length)
+        {
+            self.send_ack();
+        }
+
+        if received == length {
+            let Receiving::Body { body, .. } =
+                std::mem::replace(&mut self.receiving, Receiving::Header(Vec::new()))
+            else {
+                unreachable!()
+            };
+            check_message(&header, &body);
+            self.received.push((header, body));
+        }
+    }
+
+    /// Panics if the decoder is partway through a message, or didn't take
+    /// all the ACKs that it was owed.
+    fn assert_in_sync(&self) {
+        match &self.receiving {
+            Receiving::Header(bytes) => assert!(bytes.is_empty(), "decoder sent half a header"),
+            Receiving::Body { header, .. } => panic!("decoder didn't finish {header:?}"),
+        }
+        assert!(self.acks.is_empty(), "decoder didn't read all of its ACKs");
+    }
+}
+
+/// Checks that a message from the decoder is something the host can use.
+fn check_message(header: &MessageHeader, body: &[u8]) {
+    match header.opcode {
+        Opcode::Decode => assert!(body.len() <= MAX_FRAME_LEN),
+        Opcode::Subscribe | Opcode::Unsubscribe | Opcode::RotateKey | Opcode::Ack => {
+            assert!(body.is_empty())
+        }
+        Opcode::Info => {
+            let info = InfoResponse::decode(body.try_into().expect("decoder sent a bad info"));
+            assert!(info.free_subscriptions <= info.max_subscriptions);
+            assert!(info.storage_used <= info.storage_max);
+        }
+        Opcode::List => {
+            let entries = ListResponse::decode(body).expect("decoder sent a bad list");
+            assert!(entries.count() <= MAX_SUBSCRIPTION_COUNT);
+        }
+        Opcode::Debug | Opcode::Error => {
+            assert!(std::str::from_utf8(body).is_ok());
+        }
+    }
+}
+
+impl embedded_io::ErrorType for SimulatedHost<'_> {
+    type Error = embedded_io::ErrorKind;
+}
+
+impl embedded_io::Read for SimulatedHost<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        // ACKs are sent as soon as they're due, so they go before anything
+        // else the host has to say.
+        if let Some(ack) = self.acks.pop_front() {
+            buf[0] = ack;
+            return Ok(1);
+        }
+
+        let count = buf.len().min(self.input.len());
+        let (now, later) = self.input.split_at(count);
+        buf[..count].copy_from_slice(now);
+        self.input = later;
+        Ok(count)
+    }
+}
+
+impl embedded_io::Write for SimulatedHost<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for &byte in buf {
+            self.receive_byte(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+struct NoLed;
+
+impl StatusLed for NoLed {
+    fn red(&mut self) {}
+    fn green(&mut self) {}
+    fn cyan(&mut self) {}
+    fn magenta(&mut self) {}
+    fn yellow(&mut self) {}
+}
+
+/// Packs a message the way the host tools do.
+pub fn message(opcode: Opcode, body: &[u8]) -> Vec<u8> {
+    let mut msg = MessageHeader::new(opcode, body.len() as u16)
+        .encode()
+        .to_vec();
+    msg.extend_from_slice(body);
+    msg
+}
+
+/// Runs the decoder's command loop on a fresh decoder until the host runs out
+/// of things to say, then checks that the two sides still agree on where they
+/// are.
+pub fn run_session(input: &[u8]) -> Vec<(MessageHeader, Vec<u8>)> {
+    let mut flash = SimFlash::<STORAGE_PAGES>::new(PERSIST_BASE_ADDR);
+    let mut storage = DecoderStorage::init(&mut flash, SimEntropy::new(1), PERSIST_BASE_ADDR)
+        .expect("blank flash always initializes");
+    let mut decoder = Decoder::new(&mut storage).expect("blank flash has no key rotation");
+    let mut console = DecoderConsole(SimulatedHost::new(input));
+    // Waiting out the failed decryption delay for real would make the fuzzer
+    // useless.
+    let mut clock = MockClock::new();
+
+    // This is the same loop as the simulator's.
+    loop {
+        match run_command(&mut console, &mut decoder, &mut NoLed, &mut clock) {
+            Ok(()) => {}
+            Err(DecoderError::TransportFailed) => break,
+            Err(err) => err.write_to_console(&mut console),
+        }
+    }
+
+    // The host only ever runs out of input partway through a command of its
+    // own, so the decoder should never be left halfway through a reply.
+    console.0.assert_in_sync();
+
+    console.0.received
+}
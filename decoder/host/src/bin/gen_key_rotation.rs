@@ -0,0 +1,65 @@
+//! The Rust version of `ectf25_design.gen_key_rotation`. It takes the same
+//! arguments and writes the same kind of update.
+
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use decoder_host::{
+    cli::{generate, parse_device_id},
+    subscription::gen_key_rotation,
+};
+
+#[derive(Parser)]
+#[command(about = "Generates an update that rotates one decoder's satellite verifying key")]
+struct Args {
+    /// Force creation of key rotation file, overwriting existing file
+    #[arg(long, short)]
+    force: bool,
+
+    /// Path to the secrets file created by gen_secrets, with the key that
+    /// decoders currently accept
+    secrets_file: PathBuf,
+
+    /// Key rotation output
+    rotation_file: PathBuf,
+
+    /// Device ID of the update recipient.
+    #[arg(value_parser = parse_device_id)]
+    device_id: u32,
+
+    /// Rotation counter, which has to be higher than the last rotation's
+    counter: u32,
+
+    /// Key ID that crypto headers will name the new key by
+    key_id: u8,
+
+    /// The new Ed25519 verifying key, in hex
+    #[arg(value_parser = parse_verifying_key)]
+    verifying_key: [u8; 32],
+}
+
+fn parse_verifying_key(arg: &str) -> Result<[u8; 32], String> {
+    let mut key = [0; 32];
+    hex::decode_to_slice(arg, &mut key).or(Err(format!("{arg} isn't a 32 byte hex key")))?;
+    Ok(key)
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    generate(
+        &args.secrets_file,
+        &args.rotation_file,
+        args.force,
+        "key rotation update",
+        |secrets| {
+            gen_key_rotation(
+                secrets,
+                args.device_id,
+                args.counter,
+                args.key_id,
+                &args.verifying_key,
+            )
+        },
+    )
+}
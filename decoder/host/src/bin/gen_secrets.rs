@@ -0,0 +1,37 @@
+//! The Rust version of `ectf25_design.gen_secrets`. It takes the same arguments
+//! and writes the same secrets file.
+
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use decoder_host::cli::write_output;
+use decoder_secrets::Secrets;
+
+#[derive(Parser)]
+#[command(about = "Generates the secrets file for a deployment")]
+struct Args {
+    /// Force creation of secrets file, overwriting existing file
+    #[arg(long, short)]
+    force: bool,
+
+    /// Path to the secrets file to be created
+    secrets_file: PathBuf,
+
+    /// Supported channels. Channel 0 (broadcast) is always valid and will not
+    /// be provided in this list
+    #[arg(required = true)]
+    channels: Vec<u32>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let secrets = Secrets::generate(&args.channels);
+
+    write_output(
+        &args.secrets_file,
+        &secrets.to_json(),
+        args.force,
+        "secrets",
+    )
+}
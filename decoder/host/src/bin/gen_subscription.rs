@@ -0,0 +1,50 @@
+//! The Rust version of `ectf25_design.gen_subscription`. It takes the same
+//! arguments and writes the same subscription blob.
+
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use decoder_host::{
+    cli::{generate, parse_device_id, parse_timestamp},
+    subscription::gen_subscription,
+};
+
+#[derive(Parser)]
+#[command(about = "Generates a subscription update for one decoder")]
+struct Args {
+    /// Force creation of subscription file, overwriting existing file
+    #[arg(long, short)]
+    force: bool,
+
+    /// Path to the secrets file created by gen_secrets
+    secrets_file: PathBuf,
+
+    /// Subscription output
+    subscription_file: PathBuf,
+
+    /// Device ID of the update recipient.
+    #[arg(value_parser = parse_device_id)]
+    device_id: u32,
+
+    /// Subscription start timestamp
+    #[arg(value_parser = parse_timestamp)]
+    start: u64,
+
+    /// Subscription end timestamp
+    end: u64,
+
+    /// Channel to subscribe to
+    channel: u32,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    generate(
+        &args.secrets_file,
+        &args.subscription_file,
+        args.force,
+        "subscription",
+        |secrets| gen_subscription(secrets, args.device_id, args.start, args.end, args.channel),
+    )
+}
@@ -0,0 +1,43 @@
+//! The Rust version of `ectf25_design.gen_unsubscribe`. It takes the same
+//! arguments and writes the same kind of update.
+
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+use decoder_host::{
+    cli::{generate, parse_device_id},
+    subscription::gen_unsubscribe,
+};
+
+#[derive(Parser)]
+#[command(about = "Generates an update that revokes one decoder's subscription")]
+struct Args {
+    /// Force creation of unsubscribe file, overwriting existing file
+    #[arg(long, short)]
+    force: bool,
+
+    /// Path to the secrets file created by gen_secrets
+    secrets_file: PathBuf,
+
+    /// Unsubscribe output
+    unsubscribe_file: PathBuf,
+
+    /// Device ID of the update recipient.
+    #[arg(value_parser = parse_device_id)]
+    device_id: u32,
+
+    /// Channel to unsubscribe from
+    channel: u32,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    generate(
+        &args.secrets_file,
+        &args.unsubscribe_file,
+        args.force,
+        "unsubscribe update",
+        |secrets| gen_unsubscribe(secrets, args.device_id, args.channel),
+    )
+}
@@ -0,0 +1,72 @@
+//! What the `gen_*` binaries have in common: parsing arguments the way the
+//! Python tools do, loading the secrets file, and writing out what they made.
+
+use std::{fmt::Debug, fs::OpenOptions, io::Write, path::Path, process::ExitCode};
+
+use decoder_secrets::{parse_decoder_id, parse_int, Secrets};
+
+/// Parses a device ID like the Python tools' `int(x, 0)`.
+pub fn parse_device_id(arg: &str) -> Result<u32, String> {
+    parse_decoder_id(arg).or(Err(format!("{arg} isn't a 32 bit device ID")))
+}
+
+/// Parses a timestamp like the Python tools' `int(x, 0)`.
+pub fn parse_timestamp(arg: &str) -> Result<u64, String> {
+    parse_int(arg).ok_or(format!("{arg} isn't a timestamp"))
+}
+
+/// Loads the secrets file that gen_secrets wrote.
+pub fn load_secrets(path: &Path) -> Result<Secrets, String> {
+    std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|secrets| Secrets::from_json(&secrets).map_err(|err| format!("{err:?}")))
+        .map_err(|err| format!("Couldn't load {}: {err}", path.display()))
+}
+
+/// Writes `contents` to `path`, reporting how it went. Like the Python tools,
+/// this won't replace an existing file unless `force` is set.
+pub fn write_output(path: &Path, contents: &[u8], force: bool, what: &str) -> ExitCode {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .create_new(!force)
+        .truncate(true)
+        .open(path);
+
+    match file.and_then(|mut file| file.write_all(contents)) {
+        Ok(()) => {
+            eprintln!("Wrote {what} to {}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Couldn't write {}: {err}", path.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Loads the secrets from `secrets_file`, makes an update with them, and
+/// writes it to `output_file`.
+pub fn generate<E: Debug>(
+    secrets_file: &Path,
+    output_file: &Path,
+    force: bool,
+    what: &str,
+    make: impl FnOnce(&Secrets) -> Result<Vec<u8>, E>,
+) -> ExitCode {
+    let secrets = match load_secrets(secrets_file) {
+        Ok(secrets) => secrets,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match make(&secrets) {
+        Ok(update) => write_output(output_file, &update, force, what),
+        Err(err) => {
+            eprintln!("Couldn't make the {what}: {err:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use chacha20poly1305::{aead::AeadMutInPlace, KeyInit, XChaCha20Poly1305};
+use decoder_protocol::{
+    CryptoHeader, DecodeRequestHeader, CURRENT_SUITE, MAX_FRAME_LEN, NONCE_LEN, SIGNATURE_LEN,
+    TAG_LEN,
+};
+use ed25519_dalek::{Signer, SigningKey};
+
+use decoder_secrets::{Secrets, SecretsError};
+
+/// The largest frame that the decoder will accept.
+pub const MAX_FRAME_SIZE: usize = MAX_FRAME_LEN;
+
+#[derive(Debug)]
+pub enum EncodeError {
+    /// The deployment has no key for this channel.
+    UnknownChannel(u32),
+    /// Frames can be at most [`MAX_FRAME_SIZE`] bytes.
+    FrameTooLarge,
+    /// The AEAD refused to encrypt the frame.
+    EncryptionFailed,
+}
+
+/// Encodes frames for the decoder. This produces the same packets as
+/// `ectf25_design.encoder`:
+///
+/// `version || channel || timestamp || crypto header || XChaCha20Poly1305(frame)`
+///
+/// where `channel || timestamp` is sent in the clear as the associated data,
+/// and the signature is over `suite || key_id || channel || timestamp || nonce
+/// || ciphertext || tag`, so that the decoder can check both before
+/// decrypting.
+pub struct Encoder {
+    secrets: Secrets,
+    signing_key: SigningKey,
+}
+
+impl Encoder {
+    /// Sets up an encoder from the contents of a secrets file.
+    pub fn new(secrets: &[u8]) -> Result<Self, SecretsError> {
+        let secrets = Secrets::from_json(secrets)?;
+        let signing_key = secrets.signing_key();
+
+        Ok(Self {
+            secrets,
+            signing_key,
+        })
+    }
+
+    /// Encodes a frame with a fresh random nonce.
+    pub fn encode(
+        &self,
+        channel: u32,
+        frame: &[u8],
+        timestamp: u64,
+    ) -> Result<Vec<u8>, EncodeError> {
+        let mut nonce: [u8; NONCE_LEN] = Default::default();
+        getrandom::fill(&mut nonce).expect("the OS should always be able to give us randomness");
+
+        self.encode_with_nonce(channel, frame, timestamp, &nonce)
+    }
+
+    /// Encodes a frame with the given nonce. Never reuse a nonce, this is only
+    /// here so that packets can be reproduced exactly.
+    pub fn encode_with_nonce(
+        &self,
+        channel: u32,
+        frame: &[u8],
+        timestamp: u64,
+        nonce: &[u8; NONCE_LEN],
+    ) -> Result<Vec<u8>, EncodeError> {
+        if frame.len() > MAX_FRAME_SIZE {
+            return Err(EncodeError::FrameTooLarge);
+        }
+
+        let channel_key = self
+            .secrets
+            .channel_key(channel)
+            .ok_or(EncodeError::UnknownChannel(channel))?;
+
+        let mut header = DecodeRequestHeader {
+            channel_id: channel,
+            timestamp,
+            crypto: CryptoHeader {
+                suite: CURRENT_SUITE,
+                key_id: self.secrets.signing_key_id,
+                nonce: *nonce,
+                tag: [0; TAG_LEN],
+                signature: [0; SIGNATURE_LEN],
+            },
+        };
+        let mut payload = frame.to_vec();
+
+        let mut cipher = XChaCha20Poly1305::new(channel_key.into());
+        header.crypto.tag = cipher
+            .encrypt_in_place_detached(nonce.into(), &header.associated_data(), &mut payload)
+            .or(Err(EncodeError::EncryptionFailed))?
+            .into();
+
+        let mut signed = [0; DecodeRequestHeader::SIGNED_MAX];
+        let signed = header
+            .signed_message(&payload, &mut signed)
+            .expect("the frame length was checked above");
+        header.crypto.signature = self.signing_key.sign(signed).to_bytes();
+
+        let mut packet = Vec::with_capacity(DecodeRequestHeader::SIZE + payload.len());
+        packet.extend_from_slice(&header.encode());
+        packet.extend_from_slice(&payload);
+
+        Ok(packet)
+    }
+}
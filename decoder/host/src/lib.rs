@@ -0,0 +1,9 @@
+//! Host-side counterparts to the decoder: everything that makes packets for it
+//! rather than consuming them. These take their wire constants from
+//! decoder-protocol, the same as decoder-core, so the two sides can't quietly
+//! disagree about the format. They don't depend on decoder-core itself, which
+//! can only be built with a deployment's secrets.
+
+pub mod cli;
+pub mod encoder;
+pub mod subscription;
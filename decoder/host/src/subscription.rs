@@ -0,0 +1,233 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chacha20poly1305::{aead::AeadMutInPlace, KeyInit, XChaCha20Poly1305};
+use decoder_protocol::{
+    CryptoHeader, KeyRotationBody, KeyRotationUpdate, Opcode, SubscriptionBody, SubscriptionUpdate,
+    UnsubscribeBody, UnsubscribeUpdate, Update, CURRENT_SUITE, NONCE_LEN, SIGNATURE_LEN,
+};
+use decoder_secrets::Secrets;
+use ed25519_dalek::{Signer, VerifyingKey};
+
+#[derive(Debug)]
+pub enum SubscriptionError {
+    /// The deployment has no key for this channel.
+    UnknownChannel(u32),
+    /// Channel 0 is always decodable, so it can't be subscribed to or
+    /// unsubscribed from.
+    EmergencyChannel,
+    /// The subscription ends before it starts.
+    BadTimeRange,
+    /// The AEAD refused to encrypt the subscription.
+    EncryptionFailed,
+    /// The key to rotate to isn't one that decoders could check signatures
+    /// with.
+    BadVerifyingKey,
+    /// Rotations start at 1, since decoders that have never been rotated are
+    /// at 0.
+    BadRotationCounter,
+}
+
+/// Makes a subscription update for one decoder, with a fresh random nonce.
+/// This produces the same blobs as `ectf25_design.gen_subscription`:
+///
+/// `version || suite || key_id || nonce || tag || signature || XChaCha20Poly1305(subscription body)`
+///
+/// where the body is encrypted with the decoder's key and the signature is
+/// over the ciphertext, see [`Update`]. It is stamped with the current time, so
+/// it replaces any subscription for the channel that was made before it.
+pub fn gen_subscription(
+    secrets: &Secrets,
+    decoder_id: u32,
+    start: u64,
+    end: u64,
+    channel: u32,
+) -> Result<Vec<u8>, SubscriptionError> {
+    let mut nonce: [u8; NONCE_LEN] = Default::default();
+    getrandom::fill(&mut nonce).expect("the OS should always be able to give us randomness");
+
+    gen_subscription_with_nonce(secrets, decoder_id, start, end, channel, now(), &nonce)
+}
+
+/// Makes a subscription update with the given issue time and nonce. Never
+/// reuse a nonce, this is only here so that subscriptions can be reproduced
+/// exactly.
+pub fn gen_subscription_with_nonce(
+    secrets: &Secrets,
+    decoder_id: u32,
+    start: u64,
+    end: u64,
+    channel: u32,
+    issued: u64,
+    nonce: &[u8; NONCE_LEN],
+) -> Result<Vec<u8>, SubscriptionError> {
+    if channel == 0 {
+        return Err(SubscriptionError::EmergencyChannel);
+    }
+    if end < start {
+        return Err(SubscriptionError::BadTimeRange);
+    }
+
+    let channel_key = secrets
+        .channel_key(channel)
+        .ok_or(SubscriptionError::UnknownChannel(channel))?;
+
+    let mut body = SubscriptionBody {
+        channel_id: channel,
+        start_time: start,
+        end_time: end,
+        channel_key: *channel_key,
+        issued,
+    }
+    .encode();
+
+    let crypto = seal_for_decoder(secrets, decoder_id, Opcode::Subscribe, nonce, &mut body)?;
+    let update = SubscriptionUpdate {
+        crypto,
+        encrypted_body: body,
+    };
+
+    Ok(update.encode().to_vec())
+}
+
+/// Makes an unsubscribe update for one decoder, with a fresh random nonce.
+/// This is sealed the same way as a subscription:
+///
+/// `version || suite || key_id || nonce || tag || signature || XChaCha20Poly1305(channel_id || issued)`
+///
+/// Once the decoder applies it, it refuses every update made before it, for
+/// any channel. Subscriptions for the decoder that were made earlier but not
+/// sent yet have to be made again.
+pub fn gen_unsubscribe(
+    secrets: &Secrets,
+    decoder_id: u32,
+    channel: u32,
+) -> Result<Vec<u8>, SubscriptionError> {
+    let mut nonce: [u8; NONCE_LEN] = Default::default();
+    getrandom::fill(&mut nonce).expect("the OS should always be able to give us randomness");
+
+    gen_unsubscribe_with_nonce(secrets, decoder_id, channel, now(), &nonce)
+}
+
+/// Makes an unsubscribe update with the given issue time and nonce. Never
+/// reuse a nonce, this is only here so that updates can be reproduced exactly.
+pub fn gen_unsubscribe_with_nonce(
+    secrets: &Secrets,
+    decoder_id: u32,
+    channel: u32,
+    issued: u64,
+    nonce: &[u8; NONCE_LEN],
+) -> Result<Vec<u8>, SubscriptionError> {
+    if channel == 0 {
+        return Err(SubscriptionError::EmergencyChannel);
+    }
+
+    let mut body = UnsubscribeBody {
+        channel_id: channel,
+        issued,
+    }
+    .encode();
+
+    let crypto = seal_for_decoder(secrets, decoder_id, Opcode::Unsubscribe, nonce, &mut body)?;
+    let update = UnsubscribeUpdate {
+        crypto,
+        encrypted_body: body,
+    };
+
+    Ok(update.encode().to_vec())
+}
+
+/// Makes a key rotation update for one decoder, with a fresh random nonce.
+/// This is sealed the same way as a subscription, so it is signed with the
+/// current signing key in `secrets`:
+///
+/// `version || suite || key_id || nonce || tag || signature || XChaCha20Poly1305(counter || new key_id || new key)`
+///
+/// Once a decoder has applied it, it only accepts signatures from
+/// `verifying_key`, which crypto headers name with `key_id`.
+pub fn gen_key_rotation(
+    secrets: &Secrets,
+    decoder_id: u32,
+    counter: u32,
+    key_id: u8,
+    verifying_key: &[u8; 32],
+) -> Result<Vec<u8>, SubscriptionError> {
+    let mut nonce: [u8; NONCE_LEN] = Default::default();
+    getrandom::fill(&mut nonce).expect("the OS should always be able to give us randomness");
+
+    gen_key_rotation_with_nonce(secrets, decoder_id, counter, key_id, verifying_key, &nonce)
+}
+
+/// Makes a key rotation update with the given nonce. Never reuse a nonce, this
+/// is only here so that updates can be reproduced exactly.
+pub fn gen_key_rotation_with_nonce(
+    secrets: &Secrets,
+    decoder_id: u32,
+    counter: u32,
+    key_id: u8,
+    verifying_key: &[u8; 32],
+    nonce: &[u8; NONCE_LEN],
+) -> Result<Vec<u8>, SubscriptionError> {
+    if counter == 0 {
+        return Err(SubscriptionError::BadRotationCounter);
+    }
+    // The decoder would refuse these anyway, but it's better to find out now.
+    match VerifyingKey::from_bytes(verifying_key) {
+        Ok(key) if !key.is_weak() => {}
+        _ => return Err(SubscriptionError::BadVerifyingKey),
+    }
+
+    let mut body = KeyRotationBody {
+        counter,
+        key_id,
+        verifying_key: *verifying_key,
+    }
+    .encode();
+
+    let crypto = seal_for_decoder(secrets, decoder_id, Opcode::RotateKey, nonce, &mut body)?;
+    let update = KeyRotationUpdate {
+        crypto,
+        encrypted_body: body,
+    };
+
+    Ok(update.encode().to_vec())
+}
+
+/// The issue time for a new update, in nanoseconds since the Unix epoch, the
+/// same as Python's `time.time_ns()`.
+fn now() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("the clock should be after 1970");
+
+    since_epoch.as_nanos() as u64
+}
+
+/// Encrypts `body` in place with the decoder's key, then signs what the
+/// decoder checks before decrypting an update sent with `opcode`.
+fn seal_for_decoder(
+    secrets: &Secrets,
+    decoder_id: u32,
+    opcode: Opcode,
+    nonce: &[u8; NONCE_LEN],
+    body: &mut [u8],
+) -> Result<CryptoHeader, SubscriptionError> {
+    let mut cipher = XChaCha20Poly1305::new((&secrets.decoder_key(decoder_id)).into());
+    let tag = cipher
+        .encrypt_in_place_detached(nonce.into(), &Update::associated_data(opcode), body)
+        .or(Err(SubscriptionError::EncryptionFailed))?;
+
+    let mut crypto = CryptoHeader {
+        suite: CURRENT_SUITE,
+        key_id: secrets.signing_key_id,
+        nonce: *nonce,
+        tag: tag.into(),
+        signature: [0; SIGNATURE_LEN],
+    };
+
+    let mut signed = [0; Update::SIGNED_MAX];
+    let signed =
+        Update::signed_message(opcode, &crypto, body, &mut signed).expect("every update body fits");
+    crypto.signature = secrets.signing_key().sign(signed).to_bytes();
+
+    Ok(crypto)
+}
@@ -0,0 +1,64 @@
+//! Known answer tests for the encoder. The expected packets were made by the
+//! Python encoder (`ectf25_design.encoder`) from `core/test.secrets`, with its
+//! random nonce swapped out for a fixed one.
+
+use decoder_host::encoder::{EncodeError, Encoder};
+
+const TEST_SECRETS: &[u8] = include_bytes!("../../core/test.secrets");
+
+fn nonce_from(start: u8) -> [u8; 24] {
+    core::array::from_fn(|i| start + i as u8)
+}
+
+#[test]
+fn matches_python_encoder_for_channel_0() {
+    let encoder = Encoder::new(TEST_SECRETS).unwrap();
+    let packet = encoder
+        .encode_with_nonce(0, b"emergency broadcast", 1234, &nonce_from(0))
+        .unwrap();
+
+    assert_eq!(
+        hex::encode(packet),
+        "0500000000d2040000000000000100000102030405060708090a0b0c0d0e0f1011121314151617e7094366be\
+         23212735fad5bf551b47e3f03b2c6d7668424287ea0314fb5e966f688e165e7b32150be891977477fc3562b3\
+         5e6a55b79da20c08e18b762f0520b690016930e122991c28381aa9c6eccb06ef321d4b1f30b2fd74bfc58070\
+         525a402bfa30"
+    );
+}
+
+#[test]
+fn matches_python_encoder_for_subscribed_channel() {
+    let encoder = Encoder::new(TEST_SECRETS).unwrap();
+    let packet = encoder
+        .encode_with_nonce(3, b"channel three frame", 99, &nonce_from(100))
+        .unwrap();
+
+    assert_eq!(
+        hex::encode(packet),
+        "0503000000630000000000000001006465666768696a6b6c6d6e6f707172737475767778797a7bc939ebc140\
+         1578a75237ccc8a1b3946d5e3c048bbe066279cc250247c378d7b25cffd3c077ef4ceb316777fcfe2796f6fa\
+         e3d0b502fb0726eab5cb14a05a2643e1a5e1f0ad93628899bbcf8764a0fd059b11aed089fd869b15c80dbc20\
+         01d4c0e4f9bc"
+    );
+}
+
+#[test]
+fn refuses_unknown_channels() {
+    let encoder = Encoder::new(TEST_SECRETS).unwrap();
+
+    assert!(matches!(
+        encoder.encode(42, b"frame", 1),
+        Err(EncodeError::UnknownChannel(42))
+    ));
+}
+
+#[test]
+fn refuses_oversized_frames() {
+    let encoder = Encoder::new(TEST_SECRETS).unwrap();
+
+    assert!(matches!(
+        encoder.encode(0, &[0; 65], 1),
+        Err(EncodeError::FrameTooLarge)
+    ));
+    assert!(encoder.encode(0, &[0; 64], 1).is_ok());
+}
@@ -0,0 +1,102 @@
+//! Known answer tests for subscriptions. The expected blobs were made by
+//! `ectf25_design.gen_subscription`, `gen_unsubscribe` and `gen_key_rotation` from
+//! `core/test.secrets`, with their random nonces and issue times swapped out
+//! for fixed ones.
+
+use decoder_host::subscription::{
+    gen_key_rotation_with_nonce, gen_subscription_with_nonce, gen_unsubscribe_with_nonce,
+    SubscriptionError,
+};
+use decoder_secrets::Secrets;
+
+const TEST_SECRETS: &[u8] = include_bytes!("../../core/test.secrets");
+
+/// The next signing key's verifying key in `core/test.secrets`.
+const NEXT_VERIFYING_KEY: [u8; 32] = [
+    0x60, 0xfe, 0x29, 0x7f, 0xda, 0x98, 0x63, 0x66, 0x4e, 0x82, 0xdb, 0xde, 0x7d, 0xe7, 0x6d, 0x98,
+    0x4a, 0x8a, 0x11, 0x27, 0x5e, 0x57, 0xaf, 0x3d, 0x44, 0x7c, 0x3a, 0x6d, 0x90, 0xdb, 0xd9, 0x9b,
+];
+
+/// 2025-01-01T00:00:00Z, in nanoseconds.
+const ISSUED: u64 = 1_735_689_600_000_000_000;
+
+#[test]
+fn matches_python_gen_subscription() {
+    let secrets = Secrets::from_json(TEST_SECRETS).unwrap();
+    let nonce = core::array::from_fn(|i| i as u8);
+    let subscription =
+        gen_subscription_with_nonce(&secrets, 0xdeadbeef, 10, 1000, 1, ISSUED, &nonce).unwrap();
+
+    assert_eq!(
+        hex::encode(subscription),
+        "020100000102030405060708090a0b0c0d0e0f1011121314151617c7fe429cd4efd455ded2e2f9ad53546954\
+         aa3c3c2b1986d1a9503defd5b4807948525eb44402b58a9e2319fbe3172108c7f99a8aee66a14297d83eb09b\
+         7dfa100e04107fe68f5611f55537fae8574100497bcf723d4f0f9621b5090add8e413d7eb49ea264deab8519\
+         d02a0e325e96d9e380fd2f6056f71bf2db01181a1e595b285229a72f95b3b272c677f0"
+    );
+}
+
+#[test]
+fn matches_python_gen_unsubscribe() {
+    let secrets = Secrets::from_json(TEST_SECRETS).unwrap();
+    let nonce = core::array::from_fn(|i| i as u8);
+    let update = gen_unsubscribe_with_nonce(&secrets, 0xdeadbeef, 1, ISSUED, &nonce).unwrap();
+
+    assert_eq!(
+        hex::encode(update),
+        "020100000102030405060708090a0b0c0d0e0f1011121314151617de50af1d0dd1d34611765f64a0049b1f48\
+         b95e9aa058ab75bc4ec2330a37c8481072bd49f5845acba3051a8346a7565de89d2437861343d1c8a2a55554\
+         0ac79592dabbf62d696d4c8f770e527173a404497bcf72374f58565fdd1f12"
+    );
+}
+
+#[test]
+fn matches_python_gen_key_rotation() {
+    let secrets = Secrets::from_json(TEST_SECRETS).unwrap();
+    let nonce = core::array::from_fn(|i| i as u8);
+    let update =
+        gen_key_rotation_with_nonce(&secrets, 0xdeadbeef, 1, 1, &NEXT_VERIFYING_KEY, &nonce)
+            .unwrap();
+
+    assert_eq!(
+        hex::encode(update),
+        "020100000102030405060708090a0b0c0d0e0f10111213141516175fc89567f23f90b48d36134ead9d9d9a9e\
+         e556efd7a834e302eedbdbf8ca2be6169363909b9108cce8c8c3975014ae0385407640909bf63dc0074ddb76\
+         e26a2aa29422af405caac2a2f7c1cb680ad906497bcf72362ff1bf5e6f916953c3c3e6a0c979cf449f9cff69\
+         8d1a1105fa7f93491451544a"
+    );
+}
+
+#[test]
+fn rejects_subscriptions_the_decoder_would_not_take() {
+    let secrets = Secrets::from_json(TEST_SECRETS).unwrap();
+    let nonce = [0; 24];
+
+    assert!(matches!(
+        gen_subscription_with_nonce(&secrets, 1, 0, 10, 0, ISSUED, &nonce),
+        Err(SubscriptionError::EmergencyChannel)
+    ));
+    assert!(matches!(
+        gen_subscription_with_nonce(&secrets, 1, 0, 10, 99, ISSUED, &nonce),
+        Err(SubscriptionError::UnknownChannel(99))
+    ));
+    assert!(matches!(
+        gen_subscription_with_nonce(&secrets, 1, 10, 0, 1, ISSUED, &nonce),
+        Err(SubscriptionError::BadTimeRange)
+    ));
+    assert!(matches!(
+        gen_unsubscribe_with_nonce(&secrets, 1, 0, ISSUED, &nonce),
+        Err(SubscriptionError::EmergencyChannel)
+    ));
+    assert!(matches!(
+        gen_key_rotation_with_nonce(&secrets, 1, 0, 1, &NEXT_VERIFYING_KEY, &nonce),
+        Err(SubscriptionError::BadRotationCounter)
+    ));
+    // The identity point is a weak key.
+    let mut identity = [0; 32];
+    identity[0] = 1;
+    assert!(matches!(
+        gen_key_rotation_with_nonce(&secrets, 1, 1, 1, &identity, &nonce),
+        Err(SubscriptionError::BadVerifyingKey)
+    ));
+}
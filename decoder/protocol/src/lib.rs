@@ -0,0 +1,739 @@
+//! The packet layouts for talking to the decoder, in one place.
+//!
+//! Every message on the wire starts with a [`MessageHeader`]: the magic `%`,
+//! an [`Opcode`], and a little-endian `u16` body length. Bodies are sent in
+//! blocks of [`BLOCK_LEN`] bytes, and the receiver ACKs the header and every
+//! block. The layouts of the bodies are below, all little-endian.
+//!
+//! This is used by both the decoder and the Rust host tools, so that the two
+//! sides can't disagree about where anything is.
+#![no_std]
+
+use core::ops::RangeInclusive;
+
+/// Marks the start of every message.
+pub const MAGIC: u8 = b'%';
+
+/// The receiver ACKs each block of this many bytes.
+pub const BLOCK_LEN: usize = 256;
+
+// Crypto header fields. The nonce and tag are sized for the largest that any
+// suite needs.
+pub const NONCE_LEN: usize = 24;
+pub const TAG_LEN: usize = 16;
+pub const SIGNATURE_LEN: usize = 64;
+pub const KEY_LEN: usize = 32;
+
+/// The AEAD suite ID for XChaCha20-Poly1305, as named in a crypto header.
+pub const SUITE_XCHACHA20_POLY1305: u8 = 1;
+/// The suite that the host tools seal new packets with.
+pub const CURRENT_SUITE: u8 = SUITE_XCHACHA20_POLY1305;
+
+/// The length of a git commit hash, as reported by Info.
+pub const BUILD_HASH_LEN: usize = 20;
+
+/// The largest frame that can be sent to the decoder.
+pub const MAX_FRAME_LEN: usize = 64;
+pub const TIMESTAMP_LEN: usize = 8;
+
+/// The layout version at the start of every Decode request. The unversioned
+/// layout from before, which signed the plaintext, counts as version 1.
+/// Version 2 had the timestamp encrypted along with the frame, version 3 had
+/// no suite or key ID in its crypto header, and version 4 didn't seal or sign
+/// the version byte.
+pub const FRAME_VERSION: u8 = 5;
+
+/// The layout version at the start of every Subscribe, Unsubscribe and Rotate
+/// Key body. The unversioned layout from before, which signed the plaintext,
+/// counts as version 1.
+pub const UPDATE_VERSION: u8 = 2;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ProtocolError {
+    /// Was given the wrong number of bytes for the thing being decoded.
+    WrongLength,
+    /// A header didn't start with [`MAGIC`].
+    BadMagic,
+    /// A header had an opcode that we don't know about.
+    UnknownOpcode(u8),
+    /// A Decode request was laid out in a version that we don't know about.
+    UnknownFrameVersion(u8),
+    /// An update was laid out in a version that we don't know about.
+    UnknownUpdateVersion(u8),
+}
+
+/// The types of message on the wire.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Opcode {
+    Decode,
+    Subscribe,
+    Unsubscribe,
+    RotateKey,
+    List,
+    Info,
+    Ack,
+    Debug,
+    Error,
+}
+
+impl Opcode {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Decode => b'D',
+            Self::Subscribe => b'S',
+            Self::Unsubscribe => b'U',
+            Self::RotateKey => b'R',
+            Self::List => b'L',
+            Self::Info => b'I',
+            Self::Ack => b'A',
+            Self::Debug => b'G',
+            Self::Error => b'E',
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Result<Self, ProtocolError> {
+        match byte {
+            b'D' => Ok(Self::Decode),
+            b'S' => Ok(Self::Subscribe),
+            b'U' => Ok(Self::Unsubscribe),
+            b'R' => Ok(Self::RotateKey),
+            b'L' => Ok(Self::List),
+            b'I' => Ok(Self::Info),
+            b'A' => Ok(Self::Ack),
+            b'G' => Ok(Self::Debug),
+            b'E' => Ok(Self::Error),
+            _ => Err(ProtocolError::UnknownOpcode(byte)),
+        }
+    }
+
+    /// The body lengths that the decoder accepts for a command, or `None` if
+    /// this isn't something that the host sends as a command.
+    pub fn command_length(self) -> Option<RangeInclusive<usize>> {
+        match self {
+            Self::List | Self::Info => Some(0..=0),
+            Self::Subscribe => Some(SubscriptionUpdate::SIZE..=SubscriptionUpdate::SIZE),
+            Self::Unsubscribe => Some(UnsubscribeUpdate::SIZE..=UnsubscribeUpdate::SIZE),
+            Self::RotateKey => Some(KeyRotationUpdate::SIZE..=KeyRotationUpdate::SIZE),
+            Self::Decode => Some(DecodeRequest::MIN_SIZE..=DecodeRequest::MAX_SIZE),
+            Self::Ack | Self::Debug | Self::Error => None,
+        }
+    }
+}
+
+/// The longest body of any command, see [`Opcode::command_length`].
+pub const MAX_COMMAND_LEN: usize = {
+    let mut max = SubscriptionUpdate::SIZE;
+    if UnsubscribeUpdate::SIZE > max {
+        max = UnsubscribeUpdate::SIZE;
+    }
+    if KeyRotationUpdate::SIZE > max {
+        max = KeyRotationUpdate::SIZE;
+    }
+    if DecodeRequest::MAX_SIZE > max {
+        max = DecodeRequest::MAX_SIZE;
+    }
+    max
+};
+
+/// `magic || opcode || length`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MessageHeader {
+    pub opcode: Opcode,
+    pub length: u16,
+}
+
+impl MessageHeader {
+    pub const SIZE: usize = 4;
+
+    /// The header of an ACK, which never has a body.
+    pub const ACK: Self = Self {
+        opcode: Opcode::Ack,
+        length: 0,
+    };
+
+    pub fn new(opcode: Opcode, length: u16) -> Self {
+        Self { opcode, length }
+    }
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let length = self.length.to_le_bytes();
+        [MAGIC, self.opcode.to_byte(), length[0], length[1]]
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Result<Self, ProtocolError> {
+        if bytes[0] != MAGIC {
+            return Err(ProtocolError::BadMagic);
+        }
+
+        Ok(Self {
+            opcode: Opcode::from_byte(bytes[1])?,
+            length: u16::from_le_bytes([bytes[2], bytes[3]]),
+        })
+    }
+}
+
+/// Prefixes every encrypted payload.
+///
+/// `suite || key_id || nonce || tag || signature`
+///
+/// `suite` says which AEAD the payload is sealed with, and `key_id` which
+/// signing key it was signed with, so that either can change without breaking
+/// decoders that already know the new one. The decoder decides which IDs it
+/// accepts.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CryptoHeader {
+    pub suite: u8,
+    pub key_id: u8,
+    pub nonce: [u8; NONCE_LEN],
+    pub tag: [u8; TAG_LEN],
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl CryptoHeader {
+    pub const SIZE: usize = 1 + 1 + NONCE_LEN + TAG_LEN + SIGNATURE_LEN;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        let (ids, rest) = bytes.split_at_mut(2);
+        let (nonce, rest) = rest.split_at_mut(NONCE_LEN);
+        let (tag, signature) = rest.split_at_mut(TAG_LEN);
+        ids.copy_from_slice(&[self.suite, self.key_id]);
+        nonce.copy_from_slice(&self.nonce);
+        tag.copy_from_slice(&self.tag);
+        signature.copy_from_slice(&self.signature);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Self {
+        let (ids, rest) = bytes.split_at(2);
+        let (nonce, rest) = rest.split_at(NONCE_LEN);
+        let (tag, signature) = rest.split_at(TAG_LEN);
+        Self {
+            suite: ids[0],
+            key_id: ids[1],
+            nonce: nonce.try_into().expect("NONCE_LEN == NONCE_LEN"),
+            tag: tag.try_into().expect("TAG_LEN == TAG_LEN"),
+            signature: signature
+                .try_into()
+                .expect("SIGNATURE_LEN == SIGNATURE_LEN"),
+        }
+    }
+}
+
+/// The plaintext of a subscription update, which is sent encrypted with the
+/// decoder key.
+///
+/// `channel_id || start_time || end_time || channel_key || issued`
+///
+/// `issued` is when the update was made, in nanoseconds since the Unix epoch.
+/// The decoder refuses updates that are older than ones it already has, so
+/// that an old update can't be replayed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SubscriptionBody {
+    pub channel_id: u32,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub channel_key: [u8; KEY_LEN],
+    pub issued: u64,
+}
+
+impl SubscriptionBody {
+    pub const SIZE: usize = 4 + 8 + 8 + KEY_LEN + 8;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.channel_id.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.start_time.to_le_bytes());
+        bytes[12..20].copy_from_slice(&self.end_time.to_le_bytes());
+        bytes[20..52].copy_from_slice(&self.channel_key);
+        bytes[52..60].copy_from_slice(&self.issued.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            channel_id: u32::from_le_bytes(bytes[0..4].try_into().expect("4 == 4")),
+            start_time: u64::from_le_bytes(bytes[4..12].try_into().expect("8 == 8")),
+            end_time: u64::from_le_bytes(bytes[12..20].try_into().expect("8 == 8")),
+            channel_key: bytes[20..52].try_into().expect("KEY_LEN == KEY_LEN"),
+            issued: u64::from_le_bytes(bytes[52..60].try_into().expect("8 == 8")),
+        }
+    }
+}
+
+/// The body of a Subscribe message.
+///
+/// `version || crypto header || encrypted subscription body`
+///
+/// It is sealed and signed as described at [`Update`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SubscriptionUpdate {
+    pub crypto: CryptoHeader,
+    pub encrypted_body: [u8; SubscriptionBody::SIZE],
+}
+
+impl SubscriptionUpdate {
+    pub const SIZE: usize = Update::HEADER_SIZE + SubscriptionBody::SIZE;
+
+    /// Encodes the update as [`UPDATE_VERSION`].
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        let (header, body) = bytes.split_at_mut(Update::HEADER_SIZE);
+        header.copy_from_slice(&Update::encode_header(&self.crypto));
+        body.copy_from_slice(&self.encrypted_body);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Result<Self, ProtocolError> {
+        let (header, body) = bytes.split_at(Update::HEADER_SIZE);
+        Ok(Self {
+            crypto: Update::decode_header(header.try_into().expect("Update::HEADER_SIZE"))?,
+            encrypted_body: body.try_into().expect("SubscriptionBody::SIZE"),
+        })
+    }
+}
+
+/// The plaintext of an unsubscribe update, which is sent encrypted with the
+/// decoder key, the same as a subscription.
+///
+/// `channel_id || issued`
+///
+/// `issued` works the same way as in [`SubscriptionBody`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct UnsubscribeBody {
+    pub channel_id: u32,
+    pub issued: u64,
+}
+
+impl UnsubscribeBody {
+    pub const SIZE: usize = 4 + 8;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.channel_id.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.issued.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            channel_id: u32::from_le_bytes(bytes[0..4].try_into().expect("4 == 4")),
+            issued: u64::from_le_bytes(bytes[4..12].try_into().expect("8 == 8")),
+        }
+    }
+}
+
+/// The body of an Unsubscribe message.
+///
+/// `version || crypto header || encrypted unsubscribe body`
+///
+/// It is sealed and signed as described at [`Update`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnsubscribeUpdate {
+    pub crypto: CryptoHeader,
+    pub encrypted_body: [u8; UnsubscribeBody::SIZE],
+}
+
+impl UnsubscribeUpdate {
+    pub const SIZE: usize = Update::HEADER_SIZE + UnsubscribeBody::SIZE;
+
+    /// Encodes the update as [`UPDATE_VERSION`].
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        let (header, body) = bytes.split_at_mut(Update::HEADER_SIZE);
+        header.copy_from_slice(&Update::encode_header(&self.crypto));
+        body.copy_from_slice(&self.encrypted_body);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Result<Self, ProtocolError> {
+        let (header, body) = bytes.split_at(Update::HEADER_SIZE);
+        Ok(Self {
+            crypto: Update::decode_header(header.try_into().expect("Update::HEADER_SIZE"))?,
+            encrypted_body: body.try_into().expect("UnsubscribeBody::SIZE"),
+        })
+    }
+}
+
+/// The plaintext of a key rotation update, which is sent encrypted with the
+/// decoder key, the same as a subscription. It is signed by the key that it
+/// replaces.
+///
+/// `counter || key_id || verifying_key`
+///
+/// `counter` has to be higher than that of the last rotation that the decoder
+/// applied, so that an older key can't be put back. `key_id` is what crypto
+/// headers signed with the new key will name it by.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct KeyRotationBody {
+    pub counter: u32,
+    pub key_id: u8,
+    pub verifying_key: [u8; 32],
+}
+
+impl KeyRotationBody {
+    pub const SIZE: usize = 4 + 1 + 32;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.counter.to_le_bytes());
+        bytes[4] = self.key_id;
+        bytes[5..37].copy_from_slice(&self.verifying_key);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            counter: u32::from_le_bytes(bytes[0..4].try_into().expect("4 == 4")),
+            key_id: bytes[4],
+            verifying_key: bytes[5..37].try_into().expect("32 == 32"),
+        }
+    }
+}
+
+/// The body of a Rotate Key message.
+///
+/// `version || crypto header || encrypted key rotation body`
+///
+/// It is sealed and signed as described at [`Update`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct KeyRotationUpdate {
+    pub crypto: CryptoHeader,
+    pub encrypted_body: [u8; KeyRotationBody::SIZE],
+}
+
+impl KeyRotationUpdate {
+    pub const SIZE: usize = Update::HEADER_SIZE + KeyRotationBody::SIZE;
+
+    /// Encodes the update as [`UPDATE_VERSION`].
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        let (header, body) = bytes.split_at_mut(Update::HEADER_SIZE);
+        header.copy_from_slice(&Update::encode_header(&self.crypto));
+        body.copy_from_slice(&self.encrypted_body);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Result<Self, ProtocolError> {
+        let (header, body) = bytes.split_at(Update::HEADER_SIZE);
+        Ok(Self {
+            crypto: Update::decode_header(header.try_into().expect("Update::HEADER_SIZE"))?,
+            encrypted_body: body.try_into().expect("KeyRotationBody::SIZE"),
+        })
+    }
+}
+
+/// What the Subscribe, Unsubscribe and Rotate Key bodies have in common.
+///
+/// Each one starts with `version || crypto header`, and the rest is the
+/// update's body, encrypted with the decoder key. The version and the opcode
+/// of the message it is sent in are the associated data, see
+/// [`Self::associated_data`], so that an update can't be sent as another kind.
+/// The signature covers everything in the header but itself, and then the
+/// ciphertext, see [`Self::signed_message`], so that the decoder can check it
+/// before decrypting.
+pub struct Update;
+
+impl Update {
+    /// The length of `version || crypto header`.
+    pub const HEADER_SIZE: usize = 1 + CryptoHeader::SIZE;
+    /// The length of the associated data that an update is sealed with.
+    pub const ASSOCIATED_DATA_LEN: usize = 2;
+    /// The longest encrypted body of any update.
+    pub const MAX_BODY_LEN: usize = {
+        let mut max = SubscriptionBody::SIZE;
+        if UnsubscribeBody::SIZE > max {
+            max = UnsubscribeBody::SIZE;
+        }
+        if KeyRotationBody::SIZE > max {
+            max = KeyRotationBody::SIZE;
+        }
+        max
+    };
+    /// The longest message that an update signature can cover.
+    pub const SIGNED_MAX: usize =
+        2 + Self::ASSOCIATED_DATA_LEN + NONCE_LEN + Self::MAX_BODY_LEN + TAG_LEN;
+
+    fn encode_header(crypto: &CryptoHeader) -> [u8; Self::HEADER_SIZE] {
+        let mut bytes = [0; Self::HEADER_SIZE];
+        bytes[0] = UPDATE_VERSION;
+        bytes[1..].copy_from_slice(&crypto.encode());
+        bytes
+    }
+
+    fn decode_header(bytes: &[u8; Self::HEADER_SIZE]) -> Result<CryptoHeader, ProtocolError> {
+        if bytes[0] != UPDATE_VERSION {
+            return Err(ProtocolError::UnknownUpdateVersion(bytes[0]));
+        }
+
+        Ok(CryptoHeader::decode(
+            bytes[1..].try_into().expect("CryptoHeader::SIZE"),
+        ))
+    }
+
+    /// The associated data that an update sent with `opcode` is sealed with.
+    ///
+    /// `version || opcode`
+    pub fn associated_data(opcode: Opcode) -> [u8; Self::ASSOCIATED_DATA_LEN] {
+        [UPDATE_VERSION, opcode.to_byte()]
+    }
+
+    /// Lays out what the signature of an update sent with `opcode` covers in
+    /// `buf`, and returns the part of it that was used. The signature in
+    /// `crypto` isn't part of it, so this can be called before there is one.
+    ///
+    /// `suite || key_id || version || opcode || nonce || ciphertext || tag`
+    pub fn signed_message<'b>(
+        opcode: Opcode,
+        crypto: &CryptoHeader,
+        ciphertext: &[u8],
+        buf: &'b mut [u8; Self::SIGNED_MAX],
+    ) -> Result<&'b [u8], ProtocolError> {
+        let length = 2 + Self::ASSOCIATED_DATA_LEN + NONCE_LEN + ciphertext.len() + TAG_LEN;
+        if length > Self::SIGNED_MAX {
+            return Err(ProtocolError::WrongLength);
+        }
+
+        let (ids, rest) = buf.split_at_mut(2);
+        let (associated_data, rest) = rest.split_at_mut(Self::ASSOCIATED_DATA_LEN);
+        let (nonce, rest) = rest.split_at_mut(NONCE_LEN);
+        let (ciphertext_out, rest) = rest.split_at_mut(ciphertext.len());
+        ids.copy_from_slice(&[crypto.suite, crypto.key_id]);
+        associated_data.copy_from_slice(&Self::associated_data(opcode));
+        nonce.copy_from_slice(&crypto.nonce);
+        ciphertext_out.copy_from_slice(ciphertext);
+        rest[..TAG_LEN].copy_from_slice(&crypto.tag);
+
+        Ok(&buf[..length])
+    }
+}
+
+/// One subscription in a List response.
+///
+/// `channel_id || start_time || end_time`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ListEntry {
+    pub channel_id: u32,
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+impl ListEntry {
+    pub const SIZE: usize = 4 + 8 + 8;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.channel_id.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.start_time.to_le_bytes());
+        bytes[12..20].copy_from_slice(&self.end_time.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Self {
+        Self {
+            channel_id: u32::from_le_bytes(bytes[0..4].try_into().expect("4 == 4")),
+            start_time: u64::from_le_bytes(bytes[4..12].try_into().expect("8 == 8")),
+            end_time: u64::from_le_bytes(bytes[12..20].try_into().expect("8 == 8")),
+        }
+    }
+}
+
+/// The body of a List response.
+///
+/// `count (u32) || count * list entry`
+pub struct ListResponse;
+
+impl ListResponse {
+    /// The length of the body for a list of `count` subscriptions.
+    pub fn length(count: usize) -> usize {
+        4 + count * ListEntry::SIZE
+    }
+
+    pub fn encode_count(count: usize) -> [u8; 4] {
+        (count as u32).to_le_bytes()
+    }
+
+    /// Splits a List response body into its entries, checking that the count
+    /// matches the length.
+    pub fn decode(body: &[u8]) -> Result<impl Iterator<Item = ListEntry> + '_, ProtocolError> {
+        let (count, entries) = body
+            .split_first_chunk::<4>()
+            .ok_or(ProtocolError::WrongLength)?;
+
+        if Self::length(u32::from_le_bytes(*count) as usize) != body.len() {
+            return Err(ProtocolError::WrongLength);
+        }
+
+        Ok(entries
+            .chunks_exact(ListEntry::SIZE)
+            .map(|entry| ListEntry::decode(entry.try_into().expect("chunks_exact"))))
+    }
+}
+
+/// The body of an Info response.
+///
+/// `decoder_id || firmware_version || build_hash || max_subscriptions ||
+/// free_subscriptions || last_timestamp || storage_version || storage_used ||
+/// storage_max`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InfoResponse {
+    pub decoder_id: u32,
+    /// `major << 16 | minor << 8 | patch`
+    pub firmware_version: u32,
+    /// The git commit that the firmware was built from, or all zeros if that
+    /// isn't known.
+    pub build_hash: [u8; BUILD_HASH_LEN],
+    pub max_subscriptions: u32,
+    pub free_subscriptions: u32,
+    /// The newest frame timestamp that the decoder won't go back behind, or 0
+    /// if it hasn't decoded anything yet.
+    pub last_timestamp: u64,
+    pub storage_version: u32,
+    /// Bytes of subscription storage in use, out of `storage_max`.
+    pub storage_used: u32,
+    pub storage_max: u32,
+}
+
+impl InfoResponse {
+    pub const SIZE: usize = 4 + 4 + BUILD_HASH_LEN + 4 + 4 + 8 + 4 + 4 + 4;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.decoder_id.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.firmware_version.to_le_bytes());
+        bytes[8..28].copy_from_slice(&self.build_hash);
+        bytes[28..32].copy_from_slice(&self.max_subscriptions.to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.free_subscriptions.to_le_bytes());
+        bytes[36..44].copy_from_slice(&self.last_timestamp.to_le_bytes());
+        bytes[44..48].copy_from_slice(&self.storage_version.to_le_bytes());
+        bytes[48..52].copy_from_slice(&self.storage_used.to_le_bytes());
+        bytes[52..56].copy_from_slice(&self.storage_max.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Self {
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().expect("4 == 4"));
+        Self {
+            decoder_id: u32_at(0),
+            firmware_version: u32_at(4),
+            build_hash: bytes[8..28]
+                .try_into()
+                .expect("BUILD_HASH_LEN == BUILD_HASH_LEN"),
+            max_subscriptions: u32_at(28),
+            free_subscriptions: u32_at(32),
+            last_timestamp: u64::from_le_bytes(bytes[36..44].try_into().expect("8 == 8")),
+            storage_version: u32_at(44),
+            storage_used: u32_at(48),
+            storage_max: u32_at(52),
+        }
+    }
+}
+
+/// The start of the body of a Decode request. The rest of the body is the
+/// encrypted frame.
+///
+/// `version || channel_id || timestamp || crypto header`
+///
+/// The channel ID and timestamp are sent in the clear, so that a frame the
+/// decoder would refuse anyway can be turned away before any crypto. They are
+/// still covered by the tag, along with the version, as the associated data
+/// from [`Self::associated_data`], and by the signature, which covers
+/// everything in the header but itself, see [`Self::signed_message`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DecodeRequestHeader {
+    pub channel_id: u32,
+    pub timestamp: u64,
+    pub crypto: CryptoHeader,
+}
+
+impl DecodeRequestHeader {
+    pub const SIZE: usize = 1 + 4 + TIMESTAMP_LEN + CryptoHeader::SIZE;
+    /// The length of the associated data that a frame is sealed with.
+    pub const ASSOCIATED_DATA_LEN: usize = 1 + 4 + TIMESTAMP_LEN;
+    /// The longest message that a frame signature can cover.
+    pub const SIGNED_MAX: usize =
+        2 + Self::ASSOCIATED_DATA_LEN + NONCE_LEN + MAX_FRAME_LEN + TAG_LEN;
+
+    /// Encodes the header as [`FRAME_VERSION`].
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0] = FRAME_VERSION;
+        bytes[1..5].copy_from_slice(&self.channel_id.to_le_bytes());
+        bytes[5..13].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[13..].copy_from_slice(&self.crypto.encode());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Result<Self, ProtocolError> {
+        if bytes[0] != FRAME_VERSION {
+            return Err(ProtocolError::UnknownFrameVersion(bytes[0]));
+        }
+
+        Ok(Self {
+            channel_id: u32::from_le_bytes(bytes[1..5].try_into().expect("4 == 4")),
+            timestamp: u64::from_le_bytes(bytes[5..13].try_into().expect("8 == 8")),
+            crypto: CryptoHeader::decode(bytes[13..].try_into().expect("CryptoHeader::SIZE")),
+        })
+    }
+
+    /// The associated data that the frame is sealed with, which is the part of
+    /// the header that is sent in the clear, as [`FRAME_VERSION`].
+    ///
+    /// `version || channel_id || timestamp`
+    pub fn associated_data(&self) -> [u8; Self::ASSOCIATED_DATA_LEN] {
+        let mut bytes = [0; Self::ASSOCIATED_DATA_LEN];
+        bytes[0] = FRAME_VERSION;
+        bytes[1..5].copy_from_slice(&self.channel_id.to_le_bytes());
+        bytes[5..13].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes
+    }
+
+    /// Lays out what the signature of the frame covers in `buf`, and returns
+    /// the part of it that was used. The signature in the header isn't part of
+    /// it, so this can be called before there is one.
+    ///
+    /// `suite || key_id || version || channel_id || timestamp || nonce ||
+    /// ciphertext || tag`
+    pub fn signed_message<'b>(
+        &self,
+        ciphertext: &[u8],
+        buf: &'b mut [u8; Self::SIGNED_MAX],
+    ) -> Result<&'b [u8], ProtocolError> {
+        let length = 2 + Self::ASSOCIATED_DATA_LEN + NONCE_LEN + ciphertext.len() + TAG_LEN;
+        if length > Self::SIGNED_MAX {
+            return Err(ProtocolError::WrongLength);
+        }
+
+        let (ids, rest) = buf.split_at_mut(2);
+        let (associated_data, rest) = rest.split_at_mut(Self::ASSOCIATED_DATA_LEN);
+        let (nonce, rest) = rest.split_at_mut(NONCE_LEN);
+        let (ciphertext_out, rest) = rest.split_at_mut(ciphertext.len());
+        ids.copy_from_slice(&[self.crypto.suite, self.crypto.key_id]);
+        associated_data.copy_from_slice(&self.associated_data());
+        nonce.copy_from_slice(&self.crypto.nonce);
+        ciphertext_out.copy_from_slice(ciphertext);
+        rest[..TAG_LEN].copy_from_slice(&self.crypto.tag);
+
+        Ok(&buf[..length])
+    }
+}
+
+/// Sizes for a Decode request.
+pub struct DecodeRequest;
+
+impl DecodeRequest {
+    /// The smallest valid Decode request, which has an empty frame.
+    pub const MIN_SIZE: usize = DecodeRequestHeader::SIZE;
+    /// The largest valid Decode request, which has a full frame.
+    pub const MAX_SIZE: usize = Self::MIN_SIZE + MAX_FRAME_LEN;
+
+    /// The length of the frame in a Decode request with a body of
+    /// `packet_length` bytes.
+    pub fn frame_length(packet_length: usize) -> Result<usize, ProtocolError> {
+        if !(Self::MIN_SIZE..=Self::MAX_SIZE).contains(&packet_length) {
+            return Err(ProtocolError::WrongLength);
+        }
+
+        Ok(packet_length - Self::MIN_SIZE)
+    }
+}
@@ -0,0 +1,331 @@
+use decoder_protocol::{
+    CryptoHeader, DecodeRequest, DecodeRequestHeader, InfoResponse, KeyRotationBody,
+    KeyRotationUpdate, ListEntry, ListResponse, MessageHeader, Opcode, ProtocolError,
+    SubscriptionBody, SubscriptionUpdate, UnsubscribeBody, UnsubscribeUpdate, Update,
+    FRAME_VERSION, MAX_FRAME_LEN, UPDATE_VERSION,
+};
+
+// These sizes are what ectf25_design and the host tools expect on the wire.
+#[test]
+fn sizes_match_the_python_tools() {
+    assert_eq!(MessageHeader::SIZE, 4);
+    assert_eq!(CryptoHeader::SIZE, 1 + 1 + 24 + 16 + 64);
+    assert_eq!(SubscriptionBody::SIZE, 4 + 8 + 8 + 32 + 8);
+    assert_eq!(SubscriptionUpdate::SIZE, 1 + 106 + 60);
+    assert_eq!(UnsubscribeUpdate::SIZE, 1 + 106 + 12);
+    assert_eq!(KeyRotationUpdate::SIZE, 1 + 106 + 4 + 1 + 32);
+    assert_eq!(ListEntry::SIZE, 20);
+    assert_eq!(InfoResponse::SIZE, 56);
+    assert_eq!(DecodeRequestHeader::SIZE, 1 + 4 + 8 + 106);
+    assert_eq!(DecodeRequest::MAX_SIZE, 119 + 64);
+}
+
+#[test]
+fn message_header_round_trips() {
+    for opcode in [
+        Opcode::Decode,
+        Opcode::Subscribe,
+        Opcode::Unsubscribe,
+        Opcode::RotateKey,
+        Opcode::List,
+        Opcode::Info,
+        Opcode::Ack,
+        Opcode::Debug,
+        Opcode::Error,
+    ] {
+        let header = MessageHeader::new(opcode, 0x1234);
+        let bytes = header.encode();
+        assert_eq!(bytes[0], b'%');
+        assert_eq!(&bytes[2..], &[0x34, 0x12]);
+        assert_eq!(MessageHeader::decode(&bytes), Ok(header));
+    }
+}
+
+#[test]
+fn message_header_rejects_garbage() {
+    assert_eq!(
+        MessageHeader::decode(b"#L\0\0"),
+        Err(ProtocolError::BadMagic)
+    );
+    assert_eq!(
+        MessageHeader::decode(b"%Z\0\0"),
+        Err(ProtocolError::UnknownOpcode(b'Z'))
+    );
+}
+
+#[test]
+fn list_response_round_trips() {
+    let entries = [
+        ListEntry {
+            channel_id: 1,
+            start_time: 2,
+            end_time: 3,
+        },
+        ListEntry {
+            channel_id: 4,
+            start_time: 5,
+            end_time: u64::MAX,
+        },
+    ];
+
+    let mut body = ListResponse::encode_count(entries.len()).to_vec();
+    for entry in &entries {
+        body.extend_from_slice(&entry.encode());
+    }
+    assert_eq!(body.len(), ListResponse::length(entries.len()));
+
+    let decoded: Vec<_> = ListResponse::decode(&body).unwrap().collect();
+    assert_eq!(decoded, entries);
+
+    // A count that doesn't match the body is rejected.
+    assert!(ListResponse::decode(&body[..body.len() - 1]).is_err());
+    assert!(ListResponse::decode(&[]).is_err());
+}
+
+#[test]
+fn decode_request_frame_length() {
+    assert_eq!(DecodeRequest::frame_length(DecodeRequest::MIN_SIZE), Ok(0));
+    assert_eq!(
+        DecodeRequest::frame_length(DecodeRequest::MAX_SIZE),
+        Ok(MAX_FRAME_LEN)
+    );
+    assert_eq!(
+        DecodeRequest::frame_length(DecodeRequest::MIN_SIZE - 1),
+        Err(ProtocolError::WrongLength)
+    );
+    assert_eq!(
+        DecodeRequest::frame_length(DecodeRequest::MAX_SIZE + 1),
+        Err(ProtocolError::WrongLength)
+    );
+}
+
+#[test]
+fn decode_request_header_round_trips() {
+    let header = DecodeRequestHeader {
+        channel_id: 7,
+        timestamp: 8,
+        crypto: CryptoHeader {
+            suite: 5,
+            key_id: 6,
+            nonce: [1; 24],
+            tag: [2; 16],
+            signature: [3; 64],
+        },
+    };
+    let mut bytes = header.encode();
+    assert_eq!(bytes[0], FRAME_VERSION);
+    assert_eq!(&bytes[1..5], &7u32.to_le_bytes());
+    assert_eq!(&bytes[5..13], &8u64.to_le_bytes());
+    assert_eq!(&bytes[13..15], &[5, 6]);
+    assert_eq!(DecodeRequestHeader::decode(&bytes), Ok(header));
+
+    // The unversioned layout from before and version 2 didn't have the
+    // timestamp in the clear, version 3 had no suite or key ID, and version 4
+    // didn't seal the version.
+    for version in [1, 2, 3, 4] {
+        bytes[0] = version;
+        assert_eq!(
+            DecodeRequestHeader::decode(&bytes),
+            Err(ProtocolError::UnknownFrameVersion(version))
+        );
+    }
+}
+
+#[test]
+fn signed_message_layout() {
+    let header = DecodeRequestHeader {
+        channel_id: 7,
+        timestamp: 8,
+        crypto: CryptoHeader {
+            suite: 5,
+            key_id: 6,
+            nonce: [1; 24],
+            tag: [2; 16],
+            signature: [3; 64],
+        },
+    };
+
+    let mut buf = [0; DecodeRequestHeader::SIGNED_MAX];
+    let signed = header.signed_message(&[4; 10], &mut buf).unwrap();
+    assert_eq!(
+        signed,
+        [
+            &[5, 6][..],
+            &header.associated_data(),
+            &[1; 24],
+            &[4; 10],
+            &[2; 16]
+        ]
+        .concat()
+    );
+    assert_eq!(
+        header.associated_data(),
+        [
+            &[FRAME_VERSION][..],
+            &7u32.to_le_bytes(),
+            &8u64.to_le_bytes()
+        ]
+        .concat()[..]
+    );
+
+    let mut buf = [0; DecodeRequestHeader::SIGNED_MAX];
+    assert!(header.signed_message(&[4; MAX_FRAME_LEN], &mut buf).is_ok());
+    let mut buf = [0; DecodeRequestHeader::SIGNED_MAX];
+    assert_eq!(
+        header.signed_message(&[4; MAX_FRAME_LEN + 1], &mut buf),
+        Err(ProtocolError::WrongLength)
+    );
+}
+
+#[test]
+fn update_signed_message_layout() {
+    let crypto = CryptoHeader {
+        suite: 5,
+        key_id: 6,
+        nonce: [1; 24],
+        tag: [2; 16],
+        signature: [3; 64],
+    };
+
+    let mut buf = [0; Update::SIGNED_MAX];
+    let signed = Update::signed_message(Opcode::Unsubscribe, &crypto, &[4; 12], &mut buf).unwrap();
+    assert_eq!(
+        signed,
+        [
+            &[5, 6][..],
+            &[UPDATE_VERSION, b'U'],
+            &[1; 24],
+            &[4; 12],
+            &[2; 16]
+        ]
+        .concat()
+    );
+    assert_eq!(
+        Update::associated_data(Opcode::RotateKey),
+        [UPDATE_VERSION, b'R']
+    );
+
+    let mut buf = [0; Update::SIGNED_MAX];
+    assert!(Update::signed_message(
+        Opcode::Subscribe,
+        &crypto,
+        &[4; SubscriptionBody::SIZE],
+        &mut buf
+    )
+    .is_ok());
+    let mut buf = [0; Update::SIGNED_MAX];
+    assert_eq!(
+        Update::signed_message(
+            Opcode::Subscribe,
+            &crypto,
+            &[4; SubscriptionBody::SIZE + 1],
+            &mut buf
+        ),
+        Err(ProtocolError::WrongLength)
+    );
+}
+
+#[test]
+fn subscription_update_round_trips() {
+    let update = SubscriptionUpdate {
+        crypto: CryptoHeader {
+            suite: 5,
+            key_id: 6,
+            nonce: [1; 24],
+            tag: [2; 16],
+            signature: [3; 64],
+        },
+        encrypted_body: [4; SubscriptionBody::SIZE],
+    };
+    let mut bytes = update.encode();
+    assert_eq!(bytes[0], UPDATE_VERSION);
+    assert_eq!(&bytes[1..3], &[5, 6]);
+    assert_eq!(SubscriptionUpdate::decode(&bytes), Ok(update));
+
+    // The unversioned layout from before signed the plaintext.
+    bytes[0] = 1;
+    assert_eq!(
+        SubscriptionUpdate::decode(&bytes),
+        Err(ProtocolError::UnknownUpdateVersion(1))
+    );
+
+    let body = SubscriptionBody {
+        channel_id: 7,
+        start_time: 10,
+        end_time: 20,
+        channel_key: [9; 32],
+        issued: 30,
+    };
+    let bytes = body.encode();
+    assert_eq!(&bytes[..4], &7u32.to_le_bytes());
+    assert_eq!(&bytes[52..], &30u64.to_le_bytes());
+    assert_eq!(SubscriptionBody::decode(&bytes), body);
+}
+
+#[test]
+fn unsubscribe_update_round_trips() {
+    let update = UnsubscribeUpdate {
+        crypto: CryptoHeader {
+            suite: 5,
+            key_id: 6,
+            nonce: [1; 24],
+            tag: [2; 16],
+            signature: [3; 64],
+        },
+        encrypted_body: UnsubscribeBody {
+            channel_id: 7,
+            issued: 8,
+        }
+        .encode(),
+    };
+    let bytes = update.encode();
+    assert_eq!(&bytes[..3], &[UPDATE_VERSION, 5, 6]);
+    assert_eq!(&bytes[107..111], &7u32.to_le_bytes());
+    assert_eq!(&bytes[111..], &8u64.to_le_bytes());
+    assert_eq!(UnsubscribeUpdate::decode(&bytes), Ok(update));
+}
+
+#[test]
+fn key_rotation_update_round_trips() {
+    let body = KeyRotationBody {
+        counter: 7,
+        key_id: 8,
+        verifying_key: [9; 32],
+    };
+    let update = KeyRotationUpdate {
+        crypto: CryptoHeader {
+            suite: 5,
+            key_id: 6,
+            nonce: [1; 24],
+            tag: [2; 16],
+            signature: [3; 64],
+        },
+        encrypted_body: body.encode(),
+    };
+    let bytes = update.encode();
+    assert_eq!(&bytes[..3], &[UPDATE_VERSION, 5, 6]);
+    assert_eq!(&bytes[107..111], &7u32.to_le_bytes());
+    assert_eq!(bytes[111], 8);
+    assert_eq!(&bytes[112..], &[9; 32]);
+    assert_eq!(KeyRotationUpdate::decode(&bytes), Ok(update.clone()));
+    assert_eq!(KeyRotationBody::decode(&update.encrypted_body), body);
+}
+
+#[test]
+fn info_response_round_trips() {
+    let info = InfoResponse {
+        decoder_id: 0xdeadbeef,
+        firmware_version: 0x00010203,
+        build_hash: core::array::from_fn(|i| i as u8),
+        max_subscriptions: 8,
+        free_subscriptions: 5,
+        last_timestamp: u64::MAX - 1,
+        storage_version: 1,
+        storage_used: 100,
+        storage_max: 1024,
+    };
+    let bytes = info.encode();
+    assert_eq!(&bytes[..4], &0xdeadbeefu32.to_le_bytes());
+    assert_eq!(&bytes[52..], &1024u32.to_le_bytes());
+    assert_eq!(InfoResponse::decode(&bytes), info);
+}
@@ -0,0 +1,262 @@
+//! The deployment secrets file, and the key derivation that goes with it.
+//!
+//! decoder-core's build script bakes the secrets into the firmware, and the
+//! host tools use them to make subscriptions and frames. Both go through this
+//! crate, so the decoder key that the build derives is always the one that the
+//! subscriptions were encrypted with.
+
+use std::collections::BTreeMap;
+
+use decoder_protocol::KEY_LEN;
+use ed25519_dalek::{SigningKey, VerifyingKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+pub type Key = [u8; KEY_LEN];
+
+/// The secrets file, as written by `ectf25_design.gen_secrets`.
+#[derive(Serialize, Deserialize)]
+struct SecretsFile {
+    deployment_key: String,
+    channel_0_key: String,
+    channel_keys: BTreeMap<u32, String>,
+    salt: String,
+    signing_sk: String,
+    /// Secrets files from before key IDs were added signed with key 0.
+    #[serde(default)]
+    signing_key_id: u8,
+    #[serde(default)]
+    verifying_keys: BTreeMap<u8, String>,
+}
+
+#[derive(Debug)]
+pub enum SecretsError {
+    /// The secrets file isn't the JSON that we expected.
+    Malformed(serde_json::Error),
+    /// One of the keys wasn't valid hex, or was the wrong length.
+    BadKey(&'static str),
+    /// A decoder ID wasn't a 32 bit number.
+    BadDecoderId(String),
+    /// A verifying key was given the signing key's ID, but isn't its key.
+    KeyIdClash(u8),
+}
+
+/// The deployment's secrets, decoded and ready to use.
+pub struct Secrets {
+    pub deployment_key: Vec<u8>,
+    pub salt: Vec<u8>,
+    pub channel_0_key: Key,
+    pub channel_keys: BTreeMap<u32, Key>,
+    pub signing_sk: [u8; SECRET_KEY_LENGTH],
+    /// The key ID that `signing_sk` goes by in crypto headers.
+    pub signing_key_id: u8,
+    /// Other keys that decoders should accept signatures from, by key ID, such
+    /// as the next signing key, so that the deployment can move over to it
+    /// without rebuilding the decoders.
+    pub verifying_keys: BTreeMap<u8, [u8; PUBLIC_KEY_LENGTH]>,
+}
+
+fn decode_key<const N: usize>(hex_key: &str, name: &'static str) -> Result<[u8; N], SecretsError> {
+    hex::decode(hex_key)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or(SecretsError::BadKey(name))
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).expect("the OS should always be able to give us randomness");
+    bytes
+}
+
+impl Secrets {
+    /// Makes a fresh set of secrets for a deployment with the given channels.
+    /// Channel 0 always exists, so it doesn't need to be in `channels`.
+    pub fn generate(channels: &[u32]) -> Self {
+        Self {
+            deployment_key: random::<32>().to_vec(),
+            salt: random::<32>().to_vec(),
+            channel_0_key: random(),
+            channel_keys: channels
+                .iter()
+                .map(|&channel| (channel, random()))
+                .collect(),
+            signing_sk: SigningKey::from_bytes(&random()).to_bytes(),
+            signing_key_id: 0,
+            verifying_keys: BTreeMap::new(),
+        }
+    }
+
+    /// Parses the contents of a secrets file.
+    pub fn from_json(secrets: &[u8]) -> Result<Self, SecretsError> {
+        let file: SecretsFile = serde_json::from_slice(secrets).map_err(SecretsError::Malformed)?;
+
+        let channel_keys = file
+            .channel_keys
+            .iter()
+            .map(|(&channel, key)| Ok((channel, decode_key(key, "channel_keys")?)))
+            .collect::<Result<_, SecretsError>>()?;
+
+        let verifying_keys = file
+            .verifying_keys
+            .iter()
+            .map(|(&key_id, key)| {
+                let key = decode_key(key, "verifying_keys")?;
+                VerifyingKey::from_bytes(&key).or(Err(SecretsError::BadKey("verifying_keys")))?;
+                Ok((key_id, key))
+            })
+            .collect::<Result<_, SecretsError>>()?;
+
+        let secrets = Self {
+            deployment_key: hex::decode(file.deployment_key)
+                .or(Err(SecretsError::BadKey("deployment_key")))?,
+            salt: hex::decode(file.salt).or(Err(SecretsError::BadKey("salt")))?,
+            channel_0_key: decode_key(&file.channel_0_key, "channel_0_key")?,
+            channel_keys,
+            signing_sk: decode_key(&file.signing_sk, "signing_sk")?,
+            signing_key_id: file.signing_key_id,
+            verifying_keys,
+        };
+
+        // Having the signing key under its own ID as well is fine, but a
+        // different key under that ID would leave decoders with two keys for it.
+        let signing_vk = secrets.signing_key().verifying_key().to_bytes();
+        match secrets.verifying_keys.get(&secrets.signing_key_id) {
+            Some(key) if *key != signing_vk => {
+                Err(SecretsError::KeyIdClash(secrets.signing_key_id))
+            }
+            _ => Ok(secrets),
+        }
+    }
+
+    /// Writes the secrets out in the same format that `from_json` (and the
+    /// Python tools) read.
+    pub fn to_json(&self) -> Vec<u8> {
+        let file = SecretsFile {
+            deployment_key: hex::encode(&self.deployment_key),
+            channel_0_key: hex::encode(self.channel_0_key),
+            channel_keys: self
+                .channel_keys
+                .iter()
+                .map(|(&channel, key)| (channel, hex::encode(key)))
+                .collect(),
+            salt: hex::encode(&self.salt),
+            signing_sk: hex::encode(self.signing_sk),
+            signing_key_id: self.signing_key_id,
+            verifying_keys: self
+                .verifying_keys
+                .iter()
+                .map(|(&key_id, key)| (key_id, hex::encode(key)))
+                .collect(),
+        };
+
+        serde_json::to_vec(&file).expect("the secrets file is always valid JSON")
+    }
+
+    /// The key for a channel, if the deployment has one.
+    pub fn channel_key(&self, channel: u32) -> Option<&Key> {
+        match channel {
+            0 => Some(&self.channel_0_key),
+            _ => self.channel_keys.get(&channel),
+        }
+    }
+
+    /// The key that subscriptions for a particular decoder are encrypted with.
+    pub fn decoder_key(&self, decoder_id: u32) -> Key {
+        derive_decoder_key(&self.deployment_key, &self.salt, decoder_id)
+    }
+
+    /// The key that a particular decoder encrypts its flash storage with.
+    pub fn flash_key(&self, decoder_id: u32) -> Key {
+        derive_flash_key(&self.deployment_key, &self.salt, decoder_id)
+    }
+
+    pub fn signing_key(&self) -> SigningKey {
+        SigningKey::from_bytes(&self.signing_sk)
+    }
+
+    /// Every key that decoders should accept signatures from, by key ID. This
+    /// is the verifying keys along with the signing key's own.
+    pub fn accepted_verifying_keys(&self) -> BTreeMap<u8, [u8; PUBLIC_KEY_LENGTH]> {
+        let mut keys = self.verifying_keys.clone();
+        keys.insert(
+            self.signing_key_id,
+            self.signing_key().verifying_key().to_bytes(),
+        );
+        keys
+    }
+}
+
+/// Derives a decoder's key from the deployment key:
+///
+/// `HKDF-SHA256(salt, deployment_key, info = decoder_id as big-endian u32)`
+pub fn derive_decoder_key(deployment_key: &[u8], salt: &[u8], decoder_id: u32) -> Key {
+    let hk = Hkdf::<Sha256>::new(Some(salt), deployment_key);
+    let mut decoder_key: Key = [0; KEY_LEN];
+
+    hk.expand(&decoder_id.to_be_bytes(), &mut decoder_key)
+        .expect("32 is a valid length for SHA256");
+
+    decoder_key
+}
+
+/// Derives the key that a decoder encrypts its flash storage with:
+///
+/// `HKDF-SHA256(salt, deployment_key, info = "flash" || decoder_id as big-endian u32)`
+///
+/// This only ever goes into the decoder's own firmware, so rebuilding it for
+/// the same decoder gives it back the same key, and it can still read what it
+/// saved before.
+pub fn derive_flash_key(deployment_key: &[u8], salt: &[u8], decoder_id: u32) -> Key {
+    let hk = Hkdf::<Sha256>::new(Some(salt), deployment_key);
+    let mut flash_key: Key = [0; KEY_LEN];
+
+    hk.expand_multi_info(&[b"flash", &decoder_id.to_be_bytes()], &mut flash_key)
+        .expect("32 is a valid length for SHA256");
+
+    flash_key
+}
+
+/// Parses a decoder ID the way the Python tools do, with [`parse_int`].
+pub fn parse_decoder_id(decoder_id: &str) -> Result<u32, SecretsError> {
+    parse_int(decoder_id)
+        .and_then(|id| u32::try_from(id).ok())
+        .ok_or(SecretsError::BadDecoderId(decoder_id.to_string()))
+}
+
+/// Parses a number the way Python's `int(text, 0)` does, which is what the
+/// Python tools use for their arguments: decimal, or hex, octal or binary with
+/// a `0x`, `0o` or `0b` prefix in either case, with single `_`s allowed between
+/// digits. Negative numbers, and ones too big for a `u64`, give `None`.
+pub fn parse_int(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let text = text.strip_prefix('+').unwrap_or(text);
+
+    let prefix = text.get(..2).map(str::to_ascii_lowercase);
+    let (radix, digits) = match prefix.as_deref() {
+        Some("0x") => (16, &text[2..]),
+        Some("0o") => (8, &text[2..]),
+        Some("0b") => (2, &text[2..]),
+        _ => (10, text),
+    };
+    // Python lets an underscore come straight after the prefix.
+    let digits = match radix {
+        10 => digits,
+        _ => digits.strip_prefix('_').unwrap_or(digits),
+    };
+
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return None;
+    }
+    let digits: String = digits.chars().filter(|&c| c != '_').collect();
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    // Decimal can't have leading zeros, since they used to mean octal.
+    if radix == 10 && digits.starts_with('0') && !digits.trim_start_matches('0').is_empty() {
+        return None;
+    }
+
+    u64::from_str_radix(&digits, radix).ok()
+}
@@ -0,0 +1,157 @@
+//! The expected keys here were derived by `ectf25_design.gen_subscription`'s
+//! HKDF from `core/test.secrets`.
+
+use decoder_secrets::{
+    derive_decoder_key, derive_flash_key, parse_decoder_id, parse_int, Secrets, SecretsError,
+};
+
+const TEST_SECRETS: &[u8] = include_bytes!("../../core/test.secrets");
+
+#[test]
+fn decoder_key_matches_python() {
+    let secrets = Secrets::from_json(TEST_SECRETS).unwrap();
+
+    assert_eq!(
+        hex::encode(secrets.decoder_key(0xdeadbeef)),
+        "f28446b6ae8d8f264ea0c548f0b8dd5a589d4864dadf3daa92526da06df8121f"
+    );
+    // Short IDs are still four bytes of info, not however many digits they
+    // were written with.
+    assert_eq!(
+        hex::encode(derive_decoder_key(
+            &secrets.deployment_key,
+            &secrets.salt,
+            1
+        )),
+        "fc74e611b5a57e52ef5969f914fa1b19063d4fe88168c86f68ae36f39f18d560"
+    );
+}
+
+#[test]
+fn flash_key_is_stable_and_separate() {
+    let secrets = Secrets::from_json(TEST_SECRETS).unwrap();
+
+    // The Python tools never need this one, so these are from the same HKDF
+    // with "flash" in front of the ID.
+    assert_eq!(
+        hex::encode(secrets.flash_key(0xdeadbeef)),
+        "689acc38e94ad038a32f086f9f669ce76659e75d560f31df0e40b759a07244e7"
+    );
+    assert_eq!(
+        hex::encode(derive_flash_key(&secrets.deployment_key, &secrets.salt, 1)),
+        "75aaada2511572f140adb023424e83edf1ede486c98392783d147c141e87f6ea"
+    );
+    assert_ne!(secrets.flash_key(1), secrets.decoder_key(1));
+}
+
+#[test]
+fn decoder_ids_parse_like_python() {
+    assert_eq!(parse_decoder_id("0xdeadbeef").unwrap(), 0xdeadbeef);
+    assert_eq!(parse_decoder_id("0x1").unwrap(), 1);
+    assert_eq!(parse_decoder_id("1234").unwrap(), 1234);
+    assert!(parse_decoder_id("0x100000000").is_err());
+    assert!(parse_decoder_id("deadbeef").is_err());
+}
+
+#[test]
+fn numbers_parse_like_python_int_with_base_0() {
+    assert_eq!(parse_int("0xFF"), Some(255));
+    assert_eq!(parse_int("0XfF"), Some(255));
+    assert_eq!(parse_int("0o17"), Some(15));
+    assert_eq!(parse_int("0O17"), Some(15));
+    assert_eq!(parse_int("0b101"), Some(5));
+    assert_eq!(parse_int("0B101"), Some(5));
+    assert_eq!(parse_int("1_000_000"), Some(1_000_000));
+    assert_eq!(parse_int("0x_dead_beef"), Some(0xdeadbeef));
+    assert_eq!(parse_int(" +42 "), Some(42));
+    assert_eq!(parse_int("000"), Some(0));
+    assert_eq!(parse_int("18446744073709551615"), Some(u64::MAX));
+
+    for bad in [
+        "",
+        "0x",
+        "017",
+        "0b2",
+        "0o8",
+        "1__0",
+        "_1",
+        "1_",
+        "0x_",
+        "-1",
+        "0x-1",
+        "0x+1",
+        "1.5",
+        "18446744073709551616",
+    ] {
+        assert_eq!(parse_int(bad), None, "{bad:?} should have been refused");
+    }
+}
+
+#[test]
+fn generated_secrets_round_trip() {
+    let secrets = Secrets::generate(&[1, 3, 10]);
+    let parsed = Secrets::from_json(&secrets.to_json()).unwrap();
+
+    assert_eq!(parsed.deployment_key, secrets.deployment_key);
+    assert_eq!(parsed.salt, secrets.salt);
+    assert_eq!(parsed.channel_0_key, secrets.channel_0_key);
+    assert_eq!(parsed.channel_keys, secrets.channel_keys);
+    assert_eq!(parsed.signing_sk, secrets.signing_sk);
+
+    assert!(parsed.channel_key(0).is_some());
+    assert!(parsed.channel_key(10).is_some());
+    assert!(parsed.channel_key(2).is_none());
+}
+
+#[test]
+fn malformed_secrets_are_rejected() {
+    assert!(Secrets::from_json(b"{}").is_err());
+
+    let secrets = String::from_utf8(TEST_SECRETS.to_vec()).unwrap();
+    let bad_channel = secrets.replace("\"1\":", "\"one\":");
+    assert!(Secrets::from_json(bad_channel.as_bytes()).is_err());
+}
+
+#[test]
+fn verifying_keys_round_trip() {
+    let secrets = Secrets::from_json(TEST_SECRETS).unwrap();
+    assert_eq!(secrets.signing_key_id, 0);
+    assert!(secrets.verifying_keys.contains_key(&1));
+
+    let parsed = Secrets::from_json(&secrets.to_json()).unwrap();
+    assert_eq!(parsed.signing_key_id, secrets.signing_key_id);
+    assert_eq!(parsed.verifying_keys, secrets.verifying_keys);
+
+    // The signing key's own is always accepted, along with the others.
+    let accepted = secrets.accepted_verifying_keys();
+    assert_eq!(accepted.len(), 2);
+    assert_eq!(
+        accepted[&0],
+        secrets.signing_key().verifying_key().to_bytes()
+    );
+    assert_eq!(accepted[&1], secrets.verifying_keys[&1]);
+}
+
+#[test]
+fn secrets_from_before_key_ids_still_load() {
+    let mut secrets: serde_json::Value = serde_json::from_slice(TEST_SECRETS).unwrap();
+    let fields = secrets.as_object_mut().unwrap();
+    fields.remove("signing_key_id");
+    fields.remove("verifying_keys");
+
+    let parsed = Secrets::from_json(secrets.to_string().as_bytes()).unwrap();
+    assert_eq!(parsed.signing_key_id, 0);
+    assert!(parsed.verifying_keys.is_empty());
+    assert_eq!(parsed.accepted_verifying_keys().len(), 1);
+}
+
+#[test]
+fn verifying_keys_cant_take_the_signing_keys_id() {
+    let mut secrets = Secrets::from_json(TEST_SECRETS).unwrap();
+    secrets.signing_key_id = 1;
+
+    assert!(matches!(
+        Secrets::from_json(&secrets.to_json()),
+        Err(SecretsError::KeyIdClash(1))
+    ));
+}
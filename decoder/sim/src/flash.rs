@@ -0,0 +1,75 @@
+use std::{fs, io, path::PathBuf};
+
+use decoder_core::flash::{sim::SimFlash, FlashBackend, FlashError, STORAGE_PAGES};
+
+/// Simulated flash that is saved out to a file after every write or erase, so
+/// that the simulator keeps its subscriptions between runs the same way a board
+/// would.
+pub struct FileFlash {
+    flash: Box<SimFlash<STORAGE_PAGES>>,
+    path: PathBuf,
+}
+
+impl FileFlash {
+    /// Opens the flash image at `path`, starting with freshly erased flash if
+    /// it doesn't exist yet.
+    pub fn open(path: PathBuf, base_addr: u32) -> io::Result<Self> {
+        let mut flash = Box::new(SimFlash::new(base_addr));
+
+        match fs::read(&path) {
+            Ok(mut image) => {
+                // Images from before the storage grew are missing the pages on
+                // the end, which would have been blank anyway.
+                if image.len() < SimFlash::<STORAGE_PAGES>::SIZE {
+                    image.resize(SimFlash::<STORAGE_PAGES>::SIZE, 0xFF);
+                }
+                flash.load(&image).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "flash image must be at most {} bytes",
+                            SimFlash::<STORAGE_PAGES>::SIZE
+                        ),
+                    )
+                })?
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        Ok(Self { flash, path })
+    }
+
+    fn save(&self) -> Result<(), FlashError> {
+        let mut image = vec![0; SimFlash::<STORAGE_PAGES>::SIZE];
+        self.flash.save(&mut image)?;
+
+        // If we can't save, the board equivalent is the write not sticking.
+        fs::write(&self.path, image).or(Err(FlashError::AccessViolation))
+    }
+}
+
+impl FlashBackend for FileFlash {
+    fn read_32(&self, address: u32) -> Result<u32, FlashError> {
+        self.flash.read_32(address)
+    }
+
+    fn read_128(&self, address: u32) -> Result<[u32; 4], FlashError> {
+        self.flash.read_128(address)
+    }
+
+    fn write_32(&mut self, address: u32, data: u32) -> Result<(), FlashError> {
+        self.flash.write_32(address, data)?;
+        self.save()
+    }
+
+    fn write_128(&mut self, address: u32, data: &[u32; 4]) -> Result<(), FlashError> {
+        self.flash.write_128(address, data)?;
+        self.save()
+    }
+
+    unsafe fn erase_page(&mut self, address: u32) -> Result<(), FlashError> {
+        self.flash.erase_page(address)?;
+        self.save()
+    }
+}
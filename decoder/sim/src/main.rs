@@ -0,0 +1,198 @@
+//! A simulated decoder that runs the same command loop as the firmware, but on
+//! your own machine. It speaks the real UART protocol over a pseudo-terminal (or
+//! a TCP socket), so the ectf25 host tools can talk to it as if it were a board.
+//!
+//! The secrets come from decoder-core's build script, the same way they do for
+//! the firmware, so build this with `DECODER_ID` set and `GLOBAL_SECRETS`
+//! pointed at the deployment's secrets file.
+
+use std::{
+    io,
+    net::{TcpListener, TcpStream},
+    os::unix::fs::symlink,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use decoder_core::{
+    cmd_logic,
+    crypto::{bootstrap_crypto, EntropySource},
+    decoder::Decoder,
+    flash::{BootOutcome, DecoderStorage},
+    host_comms::{DecoderConsole, DecoderError},
+    led::StatusLed,
+    timer::Clock,
+};
+use embedded_io_adapters::std::FromStd;
+use flash::FileFlash;
+use pty::Pty;
+
+mod flash;
+mod pty;
+
+/// Where the simulated flash pretends to live. This is the same page the
+/// firmware uses.
+const PERSIST_BASE_ADDR: u32 = 0x10044000;
+
+#[derive(Parser)]
+#[command(about = "Runs a simulated decoder for the host tools to talk to")]
+struct Args {
+    /// File that the simulated flash is kept in. Created if it doesn't exist.
+    #[arg(long, default_value = "decoder_flash.bin")]
+    flash: PathBuf,
+
+    /// Listen on this address for TCP connections instead of opening a
+    /// pseudo-terminal.
+    #[arg(long)]
+    tcp: Option<String>,
+
+    /// Symlink the pseudo-terminal to this path, so scripts have a stable
+    /// port to use.
+    #[arg(long)]
+    link: Option<PathBuf>,
+
+    /// Print the LED colors as the decoder changes them.
+    #[arg(long, short)]
+    verbose: bool,
+}
+
+/// Stands in for the TRNG.
+struct OsEntropy;
+
+impl EntropySource for OsEntropy {
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        getrandom::fill(dest).expect("the OS should always be able to give us randomness");
+    }
+}
+
+/// Stands in for the RGB LED.
+struct LogLed {
+    verbose: bool,
+}
+
+impl LogLed {
+    fn set(&self, color: &str) {
+        if self.verbose {
+            eprintln!("led: {color}");
+        }
+    }
+}
+
+impl StatusLed for LogLed {
+    fn red(&mut self) {
+        self.set("red");
+    }
+
+    fn green(&mut self) {
+        self.set("green");
+    }
+
+    fn cyan(&mut self) {
+        self.set("cyan");
+    }
+
+    fn magenta(&mut self) {
+        self.set("magenta");
+    }
+
+    fn yellow(&mut self) {
+        self.set("yellow");
+    }
+}
+
+/// Stands in for SysTick.
+struct OsClock {
+    boot: Instant,
+}
+
+impl Clock for OsClock {
+    fn now_ms(&self) -> u64 {
+        self.boot.elapsed().as_millis() as u64
+    }
+
+    fn wait_until(&mut self, deadline_ms: u64) {
+        // Sleep rather than spin, there's no reason to burn a core on this.
+        let now_ms = self.now_ms();
+        if deadline_ms > now_ms {
+            thread::sleep(Duration::from_millis(deadline_ms - now_ms));
+        }
+    }
+}
+
+/// Runs commands from the host until the transport goes away.
+fn serve<T: embedded_io::Read + embedded_io::Write>(
+    console: &mut DecoderConsole<T>,
+    decoder: &mut Decoder<FileFlash, OsEntropy>,
+    led: &mut LogLed,
+    clock: &mut OsClock,
+) {
+    loop {
+        // Set light green: Ready!
+        led.green();
+
+        match cmd_logic::run_command(console, decoder, led, clock) {
+            Ok(()) => {}
+            // There's no one on the other end any more.
+            Err(DecoderError::TransportFailed) => return,
+            Err(err) => err.write_to_console(console),
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+    let mut led = LogLed {
+        verbose: args.verbose,
+    };
+
+    // Set light red: Initializing
+    led.red();
+
+    let flash = FileFlash::open(args.flash, PERSIST_BASE_ADDR)?;
+    let mut storage = DecoderStorage::init(flash, OsEntropy, PERSIST_BASE_ADDR)
+        .map_err(|err| io::Error::other(format!("couldn't read storage: {err:?}")))?;
+    if let BootOutcome::NewerVersion(version) = storage.boot_outcome() {
+        eprintln!(
+            "Storage was saved by newer firmware (version {version}), so it won't be changed"
+        );
+    }
+    let mut decoder = Decoder::new(&mut storage)
+        .map_err(|err| io::Error::other(format!("couldn't load the decoder: {err:?}")))?;
+    let mut clock = OsClock {
+        boot: Instant::now(),
+    };
+
+    bootstrap_crypto();
+
+    match args.tcp {
+        Some(addr) => {
+            let listener = TcpListener::bind(&addr)?;
+            println!("Decoder listening on {}", listener.local_addr()?);
+
+            for stream in listener.incoming() {
+                let stream: TcpStream = stream?;
+                stream.set_nodelay(true)?;
+                let mut console = DecoderConsole(FromStd::new(stream));
+                serve(&mut console, &mut decoder, &mut led, &mut clock);
+            }
+        }
+        None => {
+            let pty = Pty::open()?;
+            if let Some(link) = &args.link {
+                // Clean up after a previous run.
+                let _ = std::fs::remove_file(link);
+                symlink(&pty.path, link)?;
+            }
+            println!("Decoder listening on {}", pty.path.display());
+
+            let mut console = DecoderConsole(FromStd::new(pty.master));
+            // We hold the other end of the pty open, so this only comes back
+            // if something has gone very wrong.
+            serve(&mut console, &mut decoder, &mut led, &mut clock);
+        }
+    }
+
+    Ok(())
+}
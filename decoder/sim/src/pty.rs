@@ -0,0 +1,43 @@
+use std::{
+    fs::File,
+    io,
+    os::fd::{AsFd, OwnedFd},
+    path::PathBuf,
+};
+
+use nix::{
+    pty::openpty,
+    sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg},
+    unistd::ttyname,
+};
+
+/// A pseudo-terminal that looks like the board's serial port to the host tools.
+pub struct Pty {
+    /// Our end of the terminal, which stands in for the UART.
+    pub master: File,
+    /// We hold the other end open ourselves, otherwise reads from the master
+    /// fail with EIO every time a host tool disconnects.
+    _slave: OwnedFd,
+    /// Where the host tools should connect to.
+    pub path: PathBuf,
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Self> {
+        let pty = openpty(None, None)?;
+
+        // The host tools expect a raw 8N1 serial port, not a line-buffered
+        // terminal that echoes everything back.
+        let mut termios = tcgetattr(pty.slave.as_fd())?;
+        cfmakeraw(&mut termios);
+        tcsetattr(pty.slave.as_fd(), SetArg::TCSANOW, &termios)?;
+
+        let path = ttyname(pty.slave.as_fd())?;
+
+        Ok(Self {
+            master: File::from(pty.master),
+            _slave: pty.slave,
+            path,
+        })
+    }
+}
@@ -17,6 +17,16 @@ import struct
 import json
 from os import urandom
 
+# The layout version at the start of every frame. The unversioned layout from
+# before, which signed the plaintext, counts as version 1. Version 2 had the
+# timestamp encrypted along with the frame, version 3 had no suite or key ID in
+# its crypto header, and version 4 didn't seal or sign the version byte.
+FRAME_VERSION = 5
+
+# The ID of XChaCha20-Poly1305 in the crypto header. Decoders refuse any suite
+# whose ID they don't know.
+SUITE_XCHACHA20_POLY1305 = 1
+
 
 class Encoder:
     def __init__(self, secrets: bytes):
@@ -39,6 +49,8 @@ class Encoder:
         self.signing_sk = Ed25519PrivateKey.from_private_bytes(
             bytes.fromhex(secrets["signing_sk"])
         )
+        # Decoders pick which verifying key to check the signature with by this
+        self.signing_key_id = secrets.get("signing_key_id", 0)
 
     def encode(self, channel: int, frame: bytes, timestamp: int) -> bytes:
         """The frame encoder function
@@ -65,17 +77,34 @@ class Encoder:
 
         channel_key = bytes.fromhex(channel_key)
 
-        payload_pt = struct.pack("<Q", timestamp) + frame
+        # The channel and timestamp are sent in the clear so that the decoder
+        # can turn away frames it would refuse anyway, but are still covered by
+        # the tag, along with the version
+        associated_data = bytes([FRAME_VERSION]) + struct.pack(
+            "<IQ", channel, timestamp
+        )
 
         # Encrypt the frame
         nonce = urandom(24)
         cipher = ChaCha20_Poly1305.new(key=channel_key, nonce=nonce)
-        payload_ct, tag = cipher.encrypt_and_digest(payload_pt)
-
-        # Sign the frame
-        signature = self.signing_sk.sign(payload_pt)
+        cipher.update(associated_data)
+        payload_ct, tag = cipher.encrypt_and_digest(frame)
+
+        # Sign what the decoder sees before it decrypts, so that it can check
+        # the signature first
+        ids = bytes([SUITE_XCHACHA20_POLY1305, self.signing_key_id])
+        signature = self.signing_sk.sign(
+            ids + associated_data + nonce + payload_ct + tag
+        )
 
-        return struct.pack("<I", channel) + nonce + tag + signature + payload_ct
+        return (
+            associated_data
+            + ids
+            + nonce
+            + tag
+            + signature
+            + payload_ct
+        )
 
 
 def main():
@@ -0,0 +1,104 @@
+"""
+Generates a key rotation update, which replaces the satellite verifying key
+that a Decoder checks signatures with.
+
+It is encrypted and signed exactly like a subscription, see
+ectf25_design.gen_subscription, so it is signed with the key that it replaces.
+Once a Decoder has applied it, the Decoder only accepts signatures from the new
+key, so the secrets file should be switched over to signing with it.
+"""
+
+import argparse
+import json
+from pathlib import Path
+import struct
+
+from loguru import logger
+
+from ectf25_design.gen_subscription import seal_for_decoder
+
+
+def gen_key_rotation(
+    secrets: bytes, device_id: int, counter: int, key_id: int, verifying_key: bytes
+) -> bytes:
+    """Generate the contents of a key rotation update.
+
+    The output of this will be passed to the Decoder using ectf25.tv.rotate_key
+
+    :param secrets: Contents of the secrets file generated by ectf25_design.gen_secrets,
+        with the signing key that the Decoder currently accepts
+    :param device_id: Device ID of the Decoder
+    :param counter: Rotation counter, which must be higher than the last rotation's
+    :param key_id: Key ID that frames and updates signed with the new key will carry
+    :param verifying_key: The new 32 byte Ed25519 public key
+    """
+    if counter < 1:
+        raise ValueError("Rotation counters start at 1")
+    if len(verifying_key) != 32:
+        raise ValueError("Ed25519 public keys are 32 bytes")
+
+    # Load the json of the secrets file
+    secrets = json.loads(secrets)
+
+    # Pack the update. This will be sent to the decoder with ectf25.tv.rotate_key
+    return seal_for_decoder(
+        secrets, device_id, b"R", struct.pack("<IB", counter, key_id) + verifying_key
+    )
+
+
+def parse_args():
+    """Define and parse the command line arguments"""
+    parser = argparse.ArgumentParser()
+    parser.add_argument(
+        "--force",
+        "-f",
+        action="store_true",
+        help="Force creation of key rotation file, overwriting existing file",
+    )
+    parser.add_argument(
+        "secrets_file",
+        type=argparse.FileType("rb"),
+        help="Path to the secrets file created by ectf25_design.gen_secrets",
+    )
+    parser.add_argument("rotation_file", type=Path, help="Key rotation output")
+    parser.add_argument(
+        "device_id", type=lambda x: int(x, 0), help="Device ID of the update recipient."
+    )
+    parser.add_argument(
+        "counter",
+        type=int,
+        help="Rotation counter, which must be higher than the last rotation's",
+    )
+    parser.add_argument(
+        "key_id", type=int, help="Key ID that crypto headers will name the new key by"
+    )
+    parser.add_argument(
+        "verifying_key",
+        type=bytes.fromhex,
+        help="The new Ed25519 verifying key, in hex",
+    )
+    return parser.parse_args()
+
+
+def main():
+    """Main function of gen_key_rotation"""
+    # Parse the command line arguments
+    args = parse_args()
+
+    update = gen_key_rotation(
+        args.secrets_file.read(),
+        args.device_id,
+        args.counter,
+        args.key_id,
+        args.verifying_key,
+    )
+
+    # Open the file, erroring if the file exists unless the --force arg is provided
+    with open(args.rotation_file, "wb" if args.force else "xb") as f:
+        f.write(update)
+
+    logger.success(f"Wrote key rotation update to {str(args.rotation_file.absolute())}")
+
+
+if __name__ == "__main__":
+    main()
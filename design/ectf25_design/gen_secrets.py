@@ -48,6 +48,11 @@ def gen_secrets(channels: list[int]) -> bytes:
         "channel_keys": {channel: os.urandom(32).hex() for channel in channels},
         "salt": os.urandom(32).hex(),
         "signing_sk": signing_sk.private_bytes_raw().hex(),
+        # Which key decoders check signatures with. Keys that decoders should
+        # accept as well as this one, such as the next one to sign with, go
+        # in verifying_keys by their ID.
+        "signing_key_id": 0,
+        "verifying_keys": {},
     }
 
     # NOTE: if you choose to use JSON for your file type, you will not be able to
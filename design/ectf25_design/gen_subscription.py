@@ -15,6 +15,7 @@ import json
 from os import urandom
 from pathlib import Path
 import struct
+import time
 
 from Crypto.Cipher import ChaCha20_Poly1305
 from Crypto.Hash import SHA256
@@ -22,24 +23,31 @@ from Crypto.Protocol.KDF import HKDF
 from cryptography.hazmat.primitives.asymmetric.ed25519 import Ed25519PrivateKey
 from loguru import logger
 
+from ectf25_design.encoder import SUITE_XCHACHA20_POLY1305
 
-def gen_subscription(
-    secrets: bytes, device_id: int, start: int, end: int, channel: int
+
+# The layout version at the start of every subscription, unsubscribe and key
+# rotation update. The unversioned layout from before, which signed the
+# plaintext, counts as version 1.
+UPDATE_VERSION = 2
+
+
+def seal_for_decoder(
+    secrets: dict, device_id: int, opcode: bytes, plaintext: bytes
 ) -> bytes:
-    """Generate the contents of a subscription.
+    """Encrypt a message with a Decoder's key, then sign it.
 
-    The output of this will be passed to the Decoder using ectf25.tv.subscribe
+    Subscription, unsubscribe and key rotation updates are all sealed like this.
+    The signature covers the ciphertext, so that the Decoder can check it before
+    decrypting.
 
-    :param secrets: Contents of the secrets file generated by ectf25_design.gen_secrets
+    :param secrets: Parsed contents of the secrets file
     :param device_id: Device ID of the Decoder
-    :param start: First timestamp the subscription is valid for
-    :param end: Last timestamp the subscription is valid for
-    :param channel: Channel to enable
+    :param opcode: The message type that the update is sent to the Decoder with,
+        b"S", b"U" or b"R", so that it can't be sent as another kind
+    :param plaintext: Packed message to seal
+    :returns: version || suite || key_id || nonce || tag || signature || ciphertext
     """
-
-    # Load the json of the secrets file
-    secrets = json.loads(secrets)
-
     # Parse secrets out of the json file.
     deployment_key = bytes.fromhex(secrets["deployment_key"])
     device_id_bytes = device_id.to_bytes(4)
@@ -55,26 +63,58 @@ def gen_subscription(
         context=device_id_bytes,
     )
 
-    channel_key = bytes.fromhex(secrets["channel_keys"][str(channel)])
-
     signing_sk = Ed25519PrivateKey.from_private_bytes(
         bytes.fromhex(secrets["signing_sk"])
     )
 
-    # Pack the subscription
-    subscription_pt = struct.pack("<IQQ", channel, start, end) + channel_key
+    # The version and the message type are covered by the tag
+    associated_data = bytes([UPDATE_VERSION]) + opcode
 
-    # Encrypt the subscription
+    # Encrypt the message
     nonce = urandom(24)
     cipher = ChaCha20_Poly1305.new(key=decoder_key, nonce=nonce)
-    subscription_ct, tag = cipher.encrypt_and_digest(subscription_pt)
+    cipher.update(associated_data)
+    ciphertext, tag = cipher.encrypt_and_digest(plaintext)
+
+    # XChaCha20-Poly1305, and the key that the decoder checks the signature with
+    ids = bytes([SUITE_XCHACHA20_POLY1305, secrets.get("signing_key_id", 0)])
+
+    # Sign what the decoder sees before it decrypts, so that it can check the
+    # signature first
+    signature = signing_sk.sign(ids + associated_data + nonce + ciphertext + tag)
+
+    return bytes([UPDATE_VERSION]) + ids + nonce + tag + signature + ciphertext
+
 
-    # Sign the subscription
-    signature = signing_sk.sign(subscription_pt)
+def gen_subscription(
+    secrets: bytes, device_id: int, start: int, end: int, channel: int
+) -> bytes:
+    """Generate the contents of a subscription.
+
+    The output of this will be passed to the Decoder using ectf25.tv.subscribe
+
+    :param secrets: Contents of the secrets file generated by ectf25_design.gen_secrets
+    :param device_id: Device ID of the Decoder
+    :param start: First timestamp the subscription is valid for
+    :param end: Last timestamp the subscription is valid for
+    :param channel: Channel to enable
+    """
+
+    # Load the json of the secrets file
+    secrets = json.loads(secrets)
+
+    channel_key = bytes.fromhex(secrets["channel_keys"][str(channel)])
+
+    # Pack the subscription. The issue time at the end lets the Decoder refuse
+    # an older update for the channel after it has seen a newer one.
+    subscription_pt = (
+        struct.pack("<IQQ", channel, start, end)
+        + channel_key
+        + struct.pack("<Q", time.time_ns())
+    )
 
-    # logger.debug(f"ctlen: {len(subscription_ct)} ptlen {len(subscription_pt)}")
     # Pack the subscription. This will be sent to the decoder with ectf25.tv.subscribe
-    return nonce + tag + signature + subscription_ct
+    return seal_for_decoder(secrets, device_id, b"S", subscription_pt)
 
 
 def parse_args():
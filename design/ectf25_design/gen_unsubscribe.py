@@ -0,0 +1,83 @@
+"""
+Generates an unsubscribe update, which revokes a Decoder's subscription to a
+channel before its end timestamp.
+
+It is encrypted and signed exactly like a subscription, see
+ectf25_design.gen_subscription.
+"""
+
+import argparse
+import json
+from pathlib import Path
+import struct
+import time
+
+from loguru import logger
+
+from ectf25_design.gen_subscription import seal_for_decoder
+
+
+def gen_unsubscribe(secrets: bytes, device_id: int, channel: int) -> bytes:
+    """Generate the contents of an unsubscribe update.
+
+    The output of this will be passed to the Decoder using ectf25.tv.unsubscribe
+
+    Once the Decoder applies it, it refuses every update made before it, for any
+    channel. Subscriptions for the Decoder that were made earlier but not sent yet
+    have to be made again.
+
+    :param secrets: Contents of the secrets file generated by ectf25_design.gen_secrets
+    :param device_id: Device ID of the Decoder
+    :param channel: Channel to revoke
+    """
+    if channel == 0:
+        raise ValueError("Channel 0 can't be unsubscribed from")
+
+    # Load the json of the secrets file
+    secrets = json.loads(secrets)
+
+    # Pack the update, stamped with when it was issued like a subscription. This
+    # will be sent to the decoder with ectf25.tv.unsubscribe
+    return seal_for_decoder(
+        secrets, device_id, b"U", struct.pack("<IQ", channel, time.time_ns())
+    )
+
+
+def parse_args():
+    """Define and parse the command line arguments"""
+    parser = argparse.ArgumentParser()
+    parser.add_argument(
+        "--force",
+        "-f",
+        action="store_true",
+        help="Force creation of unsubscribe file, overwriting existing file",
+    )
+    parser.add_argument(
+        "secrets_file",
+        type=argparse.FileType("rb"),
+        help="Path to the secrets file created by ectf25_design.gen_secrets",
+    )
+    parser.add_argument("unsubscribe_file", type=Path, help="Unsubscribe output")
+    parser.add_argument(
+        "device_id", type=lambda x: int(x, 0), help="Device ID of the update recipient."
+    )
+    parser.add_argument("channel", type=int, help="Channel to unsubscribe from")
+    return parser.parse_args()
+
+
+def main():
+    """Main function of gen_unsubscribe"""
+    # Parse the command line arguments
+    args = parse_args()
+
+    update = gen_unsubscribe(args.secrets_file.read(), args.device_id, args.channel)
+
+    # Open the file, erroring if the file exists unless the --force arg is provided
+    with open(args.unsubscribe_file, "wb" if args.force else "xb") as f:
+        f.write(update)
+
+    logger.success(f"Wrote unsubscribe update to {str(args.unsubscribe_file.absolute())}")
+
+
+if __name__ == "__main__":
+    main()
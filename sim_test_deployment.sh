@@ -0,0 +1,22 @@
+#!/bin/bash
+# Same as flash_test_deployment.sh, but against the simulated decoder instead of
+# a board. Run gen_test_deployment.sh first.
+set -e
+
+cd decoder
+GLOBAL_SECRETS="$PWD/../secrets/global.secrets" DECODER_ID=0xdeadbeef \
+    cargo build -p decoder-sim
+cd ..
+
+rm -f sim_flash.bin
+./decoder/target/debug/decoder-sim --flash sim_flash.bin --link ./sim_tty &
+SIM_PID=$!
+trap 'kill $SIM_PID' EXIT
+
+sleep 1
+python -m ectf25.tv.list ./sim_tty
+python -m ectf25.tv.subscribe subscription1.bin ./sim_tty
+python -m ectf25.tv.subscribe subscription2.bin ./sim_tty
+python -m ectf25.tv.subscribe subscription3.bin ./sim_tty
+python -m ectf25.tv.subscribe subscription4.bin ./sim_tty
+python -m ectf25.tv.list ./sim_tty
@@ -0,0 +1,48 @@
+"""
+Prints what a Decoder reports about itself: its ID, firmware build, how full its
+subscription storage is, and how far through the frame timestamps it has got.
+"""
+
+import argparse
+
+from loguru import logger
+
+from ectf25.utils.decoder import DecoderIntf
+
+
+def main():
+    # Define and parse command line arguments
+    parser = argparse.ArgumentParser(
+        prog="ectf25.tv.info",
+        description="Show the identity and state of a Decoder",
+    )
+    parser.add_argument(
+        "port",
+        help="Serial port to the Decoder (see https://rules.ectf.mitre.org/2025/getting_started/boot_reference for platform-specific instructions)",
+    )
+    args = parser.parse_args()
+
+    # Open Decoder interface
+    decoder = DecoderIntf(args.port)
+
+    # Run the info command
+    info = decoder.info()
+
+    # Print the results
+    logger.info(f"Decoder ID: {info['decoder_id']:#010x}")
+    logger.info(f"Firmware: {info['firmware_version']} ({info['build_hash']})")
+    logger.info(
+        f"Subscriptions: {info['max_subscriptions'] - info['free_subscriptions']}"
+        f" of {info['max_subscriptions']} slots used"
+    )
+    logger.info(f"Last timestamp: {info['last_timestamp']}")
+    logger.info(
+        f"Storage: format version {info['storage_version']},"
+        f" {info['storage_used']} of {info['storage_max']} bytes used"
+    )
+
+    logger.success("Info successful")
+
+
+if __name__ == "__main__":
+    main()
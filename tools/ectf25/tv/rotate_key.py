@@ -0,0 +1,43 @@
+"""
+Rotates the satellite verifying key that a Decoder accepts, using an update made
+by ectf25_design.gen_key_rotation.
+"""
+
+import argparse
+
+from loguru import logger
+
+from ectf25.utils.decoder import DecoderIntf
+
+
+def main():
+    # Define and parse command line arguments
+    parser = argparse.ArgumentParser(
+        prog="ectf25.tv.rotate_key",
+        description="Rotate the satellite verifying key that a Decoder accepts",
+    )
+    parser.add_argument(
+        "rotation_file",
+        type=argparse.FileType("rb"),
+        help="Path to the key rotation file created by ectf25_design.gen_key_rotation",
+    )
+    parser.add_argument(
+        "port",
+        help="Serial port to the Decoder (see https://rules.ectf.mitre.org/2025/getting_started/boot_reference for platform-specific instructions)",
+    )
+    args = parser.parse_args()
+
+    # Read key rotation file
+    update = args.rotation_file.read()
+
+    # Open Decoder interface
+    decoder = DecoderIntf(args.port)
+
+    # Run key rotation command
+    decoder.rotate_key(update)
+
+    logger.success("Key rotation successful")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,43 @@
+"""
+Revokes one of a Decoder's subscriptions, using an update made by
+ectf25_design.gen_unsubscribe.
+"""
+
+import argparse
+
+from loguru import logger
+
+from ectf25.utils.decoder import DecoderIntf
+
+
+def main():
+    # Define and parse command line arguments
+    parser = argparse.ArgumentParser(
+        prog="ectf25.tv.unsubscribe",
+        description="Revoke one of a Decoder's subscriptions",
+    )
+    parser.add_argument(
+        "unsubscribe_file",
+        type=argparse.FileType("rb"),
+        help="Path to the unsubscribe file created by ectf25_design.gen_unsubscribe",
+    )
+    parser.add_argument(
+        "port",
+        help="Serial port to the Decoder (see https://rules.ectf.mitre.org/2025/getting_started/boot_reference for platform-specific instructions)",
+    )
+    args = parser.parse_args()
+
+    # Read unsubscribe file
+    update = args.unsubscribe_file.read()
+
+    # Open Decoder interface
+    decoder = DecoderIntf(args.port)
+
+    # Run unsubscribe command
+    decoder.unsubscribe(update)
+
+    logger.success("Unsubscribe successful")
+
+
+if __name__ == "__main__":
+    main()
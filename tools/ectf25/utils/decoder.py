@@ -27,7 +27,10 @@ class Opcode(IntEnum):
 
     DECODE = 0x44  # D
     SUBSCRIBE = 0x53  # S
+    UNSUBSCRIBE = 0x55  # U
+    ROTATE_KEY = 0x52  # R
     LIST = 0x4C  # L
+    INFO = 0x49  # I
     ACK = 0x41  # A
     DEBUG = 0x47  # G
     ERROR = 0x45  # E
@@ -152,6 +155,38 @@ class DecoderIntf:
         if resp != Message(Opcode.SUBSCRIBE, b""):
             raise DecoderError(f"Bad subscribe response {resp}")
 
+    def unsubscribe(self, update: bytes):
+        """Revoke one of the Decoder's subscriptions
+
+        :param update: Content of unsubscribe file created by
+            ectf25_design.gen_unsubscribe
+        :raises DecoderError: Error on unsubscribe failure
+        """
+        # send unsubscribe message
+        msg = Message(Opcode.UNSUBSCRIBE, update)
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        if resp != Message(Opcode.UNSUBSCRIBE, b""):
+            raise DecoderError(f"Bad unsubscribe response {resp}")
+
+    def rotate_key(self, update: bytes):
+        """Rotate the satellite verifying key that the Decoder accepts
+
+        :param update: Content of key rotation file created by
+            ectf25_design.gen_key_rotation
+        :raises DecoderError: Error on key rotation failure
+        """
+        # send key rotation message
+        msg = Message(Opcode.ROTATE_KEY, update)
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        if resp != Message(Opcode.ROTATE_KEY, b""):
+            raise DecoderError(f"Bad key rotation response {resp}")
+
     def list(self) -> list[tuple[int, int, int]]:
         """List the subscribed channels of a Decoder
 
@@ -191,6 +226,48 @@ class DecoderIntf:
 
         return channels
 
+    def info(self) -> dict[str, int | str]:
+        """Ask the Decoder about itself
+
+        :returns: A dict with the Decoder's ID, firmware version and build hash,
+            its subscription capacity, the newest timestamp it won't go back
+            behind, and its storage format version and usage
+        :raises DecoderError: Error on info failure
+        """
+        # send info message
+        msg = Message(Opcode.INFO, b"")
+        self.send_msg(msg)
+
+        # receive response
+        resp = self.get_msg()
+        fmt = "<II20sIIQIII"
+        if resp.opcode != Opcode.INFO or len(resp.body) != struct.calcsize(fmt):
+            raise DecoderError(f"Bad info response {resp}")
+
+        (
+            decoder_id,
+            version,
+            build_hash,
+            max_subscriptions,
+            free_subscriptions,
+            last_timestamp,
+            storage_version,
+            storage_used,
+            storage_max,
+        ) = struct.unpack(fmt, resp.body)
+
+        return {
+            "decoder_id": decoder_id,
+            "firmware_version": f"{version >> 16}.{(version >> 8) & 0xFF}.{version & 0xFF}",
+            "build_hash": build_hash.hex(),
+            "max_subscriptions": max_subscriptions,
+            "free_subscriptions": free_subscriptions,
+            "last_timestamp": last_timestamp,
+            "storage_version": storage_version,
+            "storage_used": storage_used,
+            "storage_max": storage_max,
+        }
+
     def send_ack(self):
         """Send an ACK to the Decoder"""
         self._open()